@@ -0,0 +1,164 @@
+use gxhash::{HashMap as GxHashMap, HashMapExt};
+
+use crate::{Analyzer, normalize, tokenize};
+
+/// Token of an alias.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AliasToken {
+    /// Position relative to the first token of the alias.
+    pub(crate) offset: u32,
+    pub(crate) token: Box<str>,
+    /// If the token can be merged with the common tokens,
+    /// the tokens stacked by the analyzer can't.
+    pub(crate) mergeable: bool,
+}
+
+/// Tokens of an alias.
+type Alias = Box<[AliasToken]>;
+
+/// Extra tokens indexed at the same position of a token, e.g.
+/// synonyms, lemmas or ASCII folded variants.
+///
+/// This allows phrase queries to match any of the forms without
+/// expanding the query, at the cost of a bigger index.
+///
+/// Aliases are analyzed like the documents and their tokens are indexed
+/// at the positions they would have in a document, starting at the
+/// position of the original token. So aliases with multiple tokens also
+/// overlap with the tokens that follow it.
+///
+/// Aliases are merged with the common tokens like the tokens of the
+/// documents, so queries that use the merged tokens still match them.
+///
+/// Used by [crate::Indexer::with_aliases].
+#[derive(Clone, Debug)]
+pub struct Aliases {
+    /// Normalized token to the tokens of each of its aliases.
+    map: GxHashMap<Box<str>, Vec<Alias>>,
+    /// Aliases as they were added, so they can be
+    /// analyzed again by the analyzer of the index.
+    added: Vec<(Box<str>, Box<str>)>,
+}
+
+impl Default for Aliases {
+    fn default() -> Self {
+        Self {
+            map: GxHashMap::new(),
+            added: Vec::new(),
+        }
+    }
+}
+
+impl Aliases {
+    /// Creates an empty set of aliases.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `alias` to be indexed alongside `token`.
+    ///
+    /// Both are normalized and tokenized the same way as the documents,
+    /// `token` has to be a single token. Returns `false` if the alias
+    /// can't be added.
+    pub fn add(&mut self, token: &str, alias: &str) -> bool {
+        let added = self.insert(token, alias, normalize, |s| {
+            let s = normalize(s);
+            tokenize(&s)
+                .zip(0..)
+                .map(|(token, offset)| AliasToken {
+                    offset,
+                    token: token.into(),
+                    mergeable: true,
+                })
+                .collect()
+        });
+        if added {
+            self.added.push((token.into(), alias.into()));
+        }
+        added
+    }
+
+    /// Same as [Self::add], but normalizing the token with `normalize` and
+    /// analyzing the alias with `analyze`, without keeping the alias.
+    fn insert(
+        &mut self,
+        token: &str,
+        alias: &str,
+        normalize: impl Fn(&str) -> String,
+        analyze: impl Fn(&str) -> Vec<AliasToken>,
+    ) -> bool {
+        let token = normalize(token);
+        let mut tokens = tokenize(&token);
+        let (Some(token), None) = (tokens.next(), tokens.next()) else {
+            return false;
+        };
+
+        let alias: Alias = analyze(alias).into();
+        if alias.is_empty() || (alias.len() == 1 && *alias[0].token == *token) {
+            return false;
+        }
+
+        let aliases = self.map.entry(token.into()).or_default();
+        if !aliases.contains(&alias) {
+            aliases.push(alias);
+        }
+        true
+    }
+
+    /// Number of tokens that have aliases.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if there are no aliases.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Normalizes and tokenizes the aliases again with the `analyzer`,
+    /// so they match the tokens of the documents.
+    ///
+    /// The case of the token is always folded, so the identifiers of source
+    /// code match as a whole. The alias is analyzed like a document, so its
+    /// tokens are at the same positions as they would be in a document.
+    pub(crate) fn normalized(&self, analyzer: &Analyzer) -> Self {
+        let mut normalized = Self {
+            added: self.added.clone(),
+            ..Self::default()
+        };
+        for (token, alias) in self.added.iter() {
+            let normalize = |s: &str| analyzer.normalize_folded(s);
+            normalized.insert(token, alias, normalize, |s| {
+                let s = analyzer.normalize(s);
+                let mut tokens = analyzer.tokenize(&s, false).peekable();
+                let first = tokens.peek().map(|token| token.pos).unwrap_or(0);
+                let mut alias = Vec::new();
+                for token in tokens {
+                    let offset = token.pos - first;
+                    alias.push(AliasToken {
+                        offset,
+                        token: token.token.into(),
+                        mergeable: token.mergeable,
+                    });
+                    if let Some(stacked) = token.stacked {
+                        alias.push(AliasToken {
+                            offset,
+                            token: stacked.into(),
+                            mergeable: false,
+                        });
+                    }
+                }
+                alias
+            });
+        }
+        normalized
+    }
+
+    /// Gets the aliases of the normalized `token`.
+    pub(crate) fn get(&self, token: &str) -> &[Alias] {
+        self.map
+            .get(token)
+            .map(|a| a.as_slice())
+            .unwrap_or_default()
+    }
+}
@@ -5,7 +5,7 @@ use std::{
 
 use rkyv::{Archive, Serialize};
 
-#[derive(Default, Archive, Serialize)]
+#[derive(Clone, Copy, Default, Archive, Serialize)]
 pub struct AlignedAllocator<const N: usize>;
 unsafe impl<const N: usize> Allocator for AlignedAllocator<N> {
     fn allocate(
@@ -0,0 +1,622 @@
+use std::{borrow::Cow, collections::VecDeque};
+
+use rkyv::{Archive, Deserialize, Serialize};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::{
+    DbError,
+    utils::{split_identifier, tokenize_code_indices, tokenize_indices},
+};
+
+#[cfg(feature = "stemming")]
+mod english;
+
+/// Languages supported by the stemmer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Language {
+    /// Porter2 (Snowball) English stemmer.
+    English,
+}
+
+/// How the stems are indexed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum StemMode {
+    /// Only the stem is indexed and searched, so `running` matches `run`,
+    /// but it's impossible to search for the exact form of a token.
+    Replace,
+    /// The token and its stem are indexed at the same position.
+    ///
+    /// The queries are stemmed by default, searching with
+    /// [crate::SearchOptions::with_exact] matches the exact form.
+    /// Stemmed queries don't use the merged common tokens, so they are
+    /// slower than the exact ones.
+    Stack,
+}
+
+/// Unicode normalization form applied to the text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Compatibility decomposition, followed by canonical composition,
+    /// e.g. full-width `１` becomes `1` and `ﬁ` becomes `fi`.
+    Nfkc,
+    /// Compatibility decomposition.
+    Nfkd,
+}
+
+/// How the tokens without any alphanumeric character
+/// (punctuation and symbols) are treated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum Punctuation {
+    /// Each punctuation is a token, so `foo, bar` doesn't match `foo bar`.
+    #[default]
+    Keep,
+    /// Punctuation is removed, so `foo, bar` matches `foo bar`.
+    Drop,
+    /// Punctuation is removed, but still takes a position, so phrases
+    /// don't match across punctuation, e.g. `end. start` doesn't match
+    /// `end start`.
+    ///
+    /// The punctuation of the queries also takes a position, so
+    /// `end. start` matches a gap of one token between them.
+    Gap,
+}
+
+/// How the runs of Chinese, Japanese and Korean characters are tokenized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum Cjk {
+    /// Same as the other scripts, by using the word boundaries. Depending
+    /// on the script this splits the text in single characters or long runs.
+    #[default]
+    Words,
+    /// Each run is split into overlapping bigrams, e.g. `東京都`
+    /// becomes `東京 京都`. Runs with a single character are kept as they are.
+    ///
+    /// Queries with a single character only match runs with a single character.
+    Bigrams,
+    /// Same as [Cjk::Bigrams], but each character is also indexed at the
+    /// position of the bigram that starts with it and the last character of
+    /// the run takes its own position, e.g. `東京都` becomes `東京 京都 都`
+    /// with `東` and `京` at the same positions as the bigrams.
+    ///
+    /// Queries with a single character match anywhere, at the cost of a bigger index.
+    UnigramsAndBigrams,
+}
+
+/// Analysis applied to the text and to each token,
+/// both while indexing and searching.
+///
+/// The analyzer is saved in the index, so the queries are
+/// always analyzed the same way as the documents.
+///
+/// By default the text is only trimmed and lowercased.
+///
+/// Used by [crate::Indexer::with_analyzer].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct Analyzer {
+    normalization: Option<Normalization>,
+    fold_diacritics: bool,
+    fold_case: bool,
+    punctuation: Punctuation,
+    cjk: Cjk,
+    code: bool,
+    stemmer: Option<(Language, StemMode)>,
+}
+
+impl Analyzer {
+    /// Creates an analyzer that keeps the tokens as they are.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the Unicode `normalization` to the text.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = Some(normalization);
+        self
+    }
+
+    /// Removes the diacritics (combining marks) of the text,
+    /// so `café` matches `cafe`.
+    ///
+    /// Scripts that use combining marks for vowels (e.g. Devanagari)
+    /// shouldn't enable this, since it changes the meaning of the words.
+    pub fn with_diacritic_folding(mut self, fold_diacritics: bool) -> Self {
+        self.fold_diacritics = fold_diacritics;
+        self
+    }
+
+    /// Uses full Unicode case folding instead of lowercasing,
+    /// e.g. `ß` matches `ss`.
+    ///
+    /// The dotted `İ` is folded into `i` followed by a combining dot,
+    /// combine it with [Self::with_diacritic_folding] to match `i`.
+    pub fn with_case_folding(mut self, fold_case: bool) -> Self {
+        self.fold_case = fold_case;
+        self
+    }
+
+    /// How the punctuation is treated, defaults to [Punctuation::Keep].
+    pub fn with_punctuation(mut self, punctuation: Punctuation) -> Self {
+        self.punctuation = punctuation;
+        self
+    }
+
+    /// How the runs of CJK characters are tokenized, defaults to [Cjk::Words].
+    pub fn with_cjk(mut self, cjk: Cjk) -> Self {
+        self.cjk = cjk;
+        self
+    }
+
+    /// Tokenizes the text as source code, so a query for `parse config`
+    /// matches `parseConfig`, `parse_config` and `PARSE_CONFIG`.
+    ///
+    /// Identifiers are split on `_` and on case boundaries, the parts are
+    /// indexed at consecutive positions and the whole identifier is indexed
+    /// at the position of the first part. A query with an identifier that
+    /// has multiple parts only matches that identifier, e.g. `parseConfig`
+    /// doesn't match `parse_config`, but it doesn't use the merged common
+    /// tokens.
+    ///
+    /// Each bracket is a token and runs of the other symbols are a single
+    /// token, e.g. `->` or `::`. Both are treated as [Punctuation].
+    pub fn with_source_code(mut self, code: bool) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Stems the tokens with the stemmer for `language`.
+    #[cfg(feature = "stemming")]
+    pub fn with_stemmer(mut self, language: Language, mode: StemMode) -> Self {
+        self.stemmer = Some((language, mode));
+        self
+    }
+
+    /// Checks if this analyzer can be used by this build of the crate.
+    pub(crate) fn check(&self) -> Result<(), DbError> {
+        if cfg!(not(feature = "stemming")) && self.stemmer.is_some() {
+            return Err(DbError::FeatureNotEnabled("stemming".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Normalizes the text of a document or query, before it's tokenized.
+    ///
+    /// The case of source code is folded by [Self::tokenize],
+    /// after the identifiers are split.
+    pub(crate) fn normalize(&self, s: &str) -> String {
+        self.normalize_with(s.trim_start().trim_end(), !self.code)
+    }
+
+    /// Same as [Self::normalize], but always folds the case.
+    pub(crate) fn normalize_folded(&self, s: &str) -> String {
+        self.normalize_with(s.trim_start().trim_end(), true)
+    }
+
+    fn normalize_with(&self, s: &str, fold: bool) -> String {
+        let s: String = match self.normalization {
+            Some(_) => s.nfkd().collect(),
+            None if self.fold_diacritics => s.nfd().collect(),
+            None if fold => return self.fold(s),
+            None => return s.to_string(),
+        };
+
+        let mut s = match fold {
+            true => self.fold(&s),
+            false => s,
+        };
+        if self.fold_diacritics {
+            s.retain(|c| !is_combining_mark(c));
+        }
+
+        // folding the case can undo the normalization
+        match self.normalization {
+            Some(Normalization::Nfkc) => s.nfkc().collect(),
+            Some(Normalization::Nfkd) => s.nfkd().collect(),
+            None => s.nfc().collect(),
+        }
+    }
+
+    /// Lowercases or folds the case of `s`.
+    #[inline(always)]
+    fn fold(&self, s: &str) -> String {
+        match self.fold_case {
+            true => caseless::default_case_fold_str(s),
+            false => s.to_lowercase(),
+        }
+    }
+
+    /// Tokenizes the normalized text.
+    ///
+    /// If `query` the last character of a CJK run at the end of the text
+    /// is not emitted by [Cjk::UnigramsAndBigrams], since the run can
+    /// continue in the documents, and the identifiers of source code are
+    /// emitted as a whole followed by their remaining parts. The queries
+    /// should ignore the stacked tokens.
+    pub(crate) fn tokenize<'a>(&self, s: &'a str, query: bool) -> impl Iterator<Item = Token<'a>> {
+        let punctuation = self.punctuation;
+        let cjk = self.cjk;
+        let segments: Box<dyn Iterator<Item = (usize, &'a str)> + 'a> = match self.code {
+            true => Box::new(tokenize_code_indices(s)),
+            false => Box::new(tokenize_indices(s)),
+        };
+        let mut segments = segments.peekable();
+        let mut pending = VecDeque::new();
+        let mut next_pos = 0;
+        std::iter::from_fn(move || {
+            loop {
+                if let Some(token) = pending.pop_front() {
+                    return Some(token);
+                }
+
+                let (begin, token) = segments.next()?;
+                if cjk != Cjk::Words && is_cjk(token) {
+                    let mut end = begin + token.len();
+                    while let Some((_, token)) =
+                        segments.next_if(|(begin, token)| *begin == end && is_cjk(token))
+                    {
+                        end += token.len();
+                    }
+                    let unigrams =
+                        cjk == Cjk::UnigramsAndBigrams && (!query || segments.peek().is_some());
+                    next_pos = bigrams(&s[begin..end], next_pos, unigrams, &mut pending);
+                    continue;
+                }
+
+                let pos = next_pos;
+                if !token.chars().any(char::is_alphanumeric) {
+                    match punctuation {
+                        Punctuation::Keep => {}
+                        Punctuation::Drop => continue,
+                        Punctuation::Gap => {
+                            next_pos += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                if self.code {
+                    next_pos = self.identifier(token, pos, query, &mut pending);
+                    continue;
+                }
+
+                next_pos += 1;
+                return Some(Token::new(pos, Cow::Borrowed(token)));
+            }
+        })
+    }
+
+    /// Splits an `identifier` of source code into its parts, starting at
+    /// position `pos`. Returns the position after the end of the identifier.
+    fn identifier<'a>(
+        &self,
+        identifier: &'a str,
+        mut pos: u32,
+        query: bool,
+        tokens: &mut VecDeque<Token<'a>>,
+    ) -> u32 {
+        let whole = Cow::Owned(self.normalize_folded(identifier));
+        let mut parts = split_identifier(identifier).map(|p| Cow::Owned(self.normalize_folded(p)));
+        let (Some(first), Some(second)) = (parts.next(), parts.next()) else {
+            tokens.push_back(Token::new(pos, whole));
+            return pos + 1;
+        };
+
+        tokens.push_back(match query {
+            // the first part is at the same position, so it's implied
+            true => Token {
+                pos,
+                token: whole,
+                stacked: None,
+                mergeable: false,
+            },
+            false => Token {
+                pos,
+                token: first,
+                stacked: Some(whole),
+                mergeable: true,
+            },
+        });
+        for part in std::iter::once(second).chain(parts) {
+            pos += 1;
+            tokens.push_back(Token::new(pos, part));
+        }
+        pos + 1
+    }
+
+    /// Analyzes a `token` of a document, returning the token to be indexed
+    /// and the token to be indexed at the same position, if any.
+    pub(crate) fn index_token<'a>(&self, token: &'a str) -> (Cow<'a, str>, Option<Cow<'a, str>>) {
+        match self.stemmer {
+            None => (Cow::Borrowed(token), None),
+            Some((language, StemMode::Replace)) => (stem(language, token), None),
+            Some((language, StemMode::Stack)) => match stem(language, token) {
+                Cow::Borrowed(_) => (Cow::Borrowed(token), None),
+                stem => (Cow::Borrowed(token), Some(stem)),
+            },
+        }
+    }
+
+    /// Analyzes a `token` of a query, if `exact` the token is
+    /// kept as it is, when the index allows it.
+    pub(crate) fn query_token<'a>(&self, token: &'a str, exact: bool) -> Cow<'a, str> {
+        match self.stemmer {
+            None => Cow::Borrowed(token),
+            Some((_, StemMode::Stack)) if exact => Cow::Borrowed(token),
+            Some((language, _)) => stem(language, token),
+        }
+    }
+
+    /// Returns `true` if the analyzed queries can use
+    /// the merged common tokens.
+    pub(crate) fn uses_common_tokens(&self, exact: bool) -> bool {
+        !matches!(self.stemmer, Some((_, StemMode::Stack))) || exact
+    }
+}
+
+/// Token emitted by [Analyzer::tokenize].
+pub(crate) struct Token<'a> {
+    pub(crate) pos: u32,
+    pub(crate) token: Cow<'a, str>,
+    /// Token to be indexed at the same position, if any.
+    pub(crate) stacked: Option<Cow<'a, str>>,
+    /// If the token is part of the tokenized document,
+    /// so it can be merged with the common tokens.
+    pub(crate) mergeable: bool,
+}
+
+impl<'a> Token<'a> {
+    fn new(pos: u32, token: Cow<'a, str>) -> Self {
+        Self {
+            pos,
+            token,
+            stacked: None,
+            mergeable: true,
+        }
+    }
+}
+
+/// Returns `true` if all of the characters of `token` are
+/// Chinese, Japanese or Korean letters.
+fn is_cjk(token: &str) -> bool {
+    token.chars().all(|c| {
+        c.is_alphanumeric()
+            && matches!(c,
+                '\u{1100}'..='\u{11FF}' // Hangul Jamo
+                | '\u{2E80}'..='\u{2FDF}' // CJK and Kangxi radicals
+                | '\u{3040}'..='\u{30FF}' // Hiragana and Katakana
+                | '\u{3130}'..='\u{318F}' // Hangul compatibility Jamo
+                | '\u{31F0}'..='\u{31FF}' // Katakana phonetic extensions
+                | '\u{3400}'..='\u{4DBF}' // CJK unified ideographs extension A
+                | '\u{4E00}'..='\u{9FFF}' // CJK unified ideographs
+                | '\u{AC00}'..='\u{D7AF}' // Hangul syllables
+                | '\u{F900}'..='\u{FAFF}' // CJK compatibility ideographs
+                | '\u{FF66}'..='\u{FF9F}' // Halfwidth Katakana
+                | '\u{20000}'..='\u{2FA1F}' // CJK unified ideographs extensions
+            )
+    })
+}
+
+/// Splits a `run` of CJK characters into bigrams, starting at position `pos`,
+/// if `unigrams` each character is also emitted. Returns the position after
+/// the end of the run.
+fn bigrams<'a>(run: &'a str, mut pos: u32, unigrams: bool, grams: &mut VecDeque<Token<'a>>) -> u32 {
+    let bounds: Vec<usize> = run
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(run.len()))
+        .collect();
+
+    if bounds.len() <= 2 {
+        grams.push_back(Token::new(pos, Cow::Borrowed(run)));
+        return pos + 1;
+    }
+
+    for [b, m, e] in bounds.array_windows() {
+        let mut bigram = Token::new(pos, Cow::Borrowed(&run[*b..*e]));
+        bigram.stacked = unigrams.then(|| Cow::Borrowed(&run[*b..*m]));
+        grams.push_back(bigram);
+        pos += 1;
+    }
+
+    if unigrams {
+        // this can't fail, there are at least 3 bounds
+        let last = bounds[bounds.len() - 2];
+        grams.push_back(Token::new(pos, Cow::Borrowed(&run[last..])));
+        pos += 1;
+    }
+    pos
+}
+
+/// Stems `token`, returning [Cow::Borrowed] if the stem is the token itself.
+#[cfg(feature = "stemming")]
+#[inline(always)]
+fn stem(language: Language, token: &str) -> Cow<'_, str> {
+    match language {
+        Language::English => english::stem(token),
+    }
+}
+
+/// Indexes that use stemming can't be opened without the feature,
+/// so this is never called with a [Language].
+#[cfg(not(feature = "stemming"))]
+#[inline(always)]
+fn stem(_: Language, token: &str) -> Cow<'_, str> {
+    Cow::Borrowed(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Analyzer, Cjk, Normalization, Punctuation};
+
+    /// Position, token and stacked token of each token of `s`.
+    fn tokens(analyzer: &Analyzer, s: &str, query: bool) -> Vec<(u32, String, Option<String>)> {
+        let s = analyzer.normalize(s);
+        analyzer
+            .tokenize(&s, query)
+            .map(|t| (t.pos, t.token.into(), t.stacked.map(|s| s.into())))
+            .collect()
+    }
+
+    fn plain(tokens: &[(u32, &str)]) -> Vec<(u32, String, Option<String>)> {
+        tokens
+            .iter()
+            .map(|(pos, token)| (*pos, token.to_string(), None))
+            .collect()
+    }
+
+    #[test]
+    fn normalization() {
+        let analyzer = Analyzer::new();
+        assert_eq!(analyzer.normalize("  Café ﬁ １ "), "café ﬁ １");
+
+        let nfkc = Analyzer::new().with_normalization(Normalization::Nfkc);
+        assert_eq!(nfkc.normalize("Cafe\u{301} ﬁ １"), "caf\u{e9} fi 1");
+
+        let nfkd = Analyzer::new().with_normalization(Normalization::Nfkd);
+        assert_eq!(nfkd.normalize("Caf\u{e9} ﬁ １"), "cafe\u{301} fi 1");
+
+        let folded = nfkd.clone().with_diacritic_folding(true);
+        assert_eq!(folded.normalize("Caf\u{e9} naïve"), "cafe naive");
+    }
+
+    #[test]
+    fn case_folding() {
+        assert_eq!(Analyzer::new().normalize("STRAẞE Straße"), "straße straße");
+        let caseless = Analyzer::new().with_case_folding(true);
+        assert_eq!(caseless.normalize("STRAẞE Straße"), "strasse strasse");
+    }
+
+    #[test]
+    fn punctuation() {
+        let keep = Analyzer::new().with_punctuation(Punctuation::Keep);
+        assert_eq!(
+            tokens(&keep, "a, b", false),
+            plain(&[(0, "a"), (1, ","), (2, "b")])
+        );
+
+        let drop = Analyzer::new().with_punctuation(Punctuation::Drop);
+        assert_eq!(tokens(&drop, "a, b", false), plain(&[(0, "a"), (1, "b")]));
+
+        // the punctuation leaves a hole in the positions
+        let gap = Analyzer::new().with_punctuation(Punctuation::Gap);
+        assert_eq!(tokens(&gap, "a, b", false), plain(&[(0, "a"), (2, "b")]));
+        assert_eq!(tokens(&gap, "a, b", true), plain(&[(0, "a"), (2, "b")]));
+    }
+
+    #[test]
+    fn cjk() {
+        let words = Analyzer::new().with_cjk(Cjk::Words);
+        assert_eq!(
+            tokens(&words, "東京都", false),
+            plain(&[(0, "東"), (1, "京"), (2, "都")])
+        );
+
+        let bigrams = Analyzer::new().with_cjk(Cjk::Bigrams);
+        assert_eq!(
+            tokens(&bigrams, "東京都 a 京", false),
+            plain(&[(0, "東京"), (1, "京都"), (2, "a"), (3, "京")])
+        );
+
+        let unigrams = Analyzer::new().with_cjk(Cjk::UnigramsAndBigrams);
+        assert_eq!(
+            tokens(&unigrams, "東京都 a", false),
+            [
+                (0, "東京".into(), Some("東".into())),
+                (1, "京都".into(), Some("京".into())),
+                (2, "都".into(), None),
+                (3, "a".into(), None),
+            ]
+        );
+        // the run can continue in the documents
+        assert_eq!(
+            tokens(&unigrams, "東京都", true),
+            plain(&[(0, "東京"), (1, "京都")])
+        );
+    }
+
+    #[test]
+    fn source_code() {
+        let code = Analyzer::new().with_source_code(true);
+        assert_eq!(
+            tokens(&code, "parseConfig(PARSE_CONFIG)", false),
+            [
+                (0, "parse".into(), Some("parseconfig".into())),
+                (1, "config".into(), None),
+                (2, "(".into(), None),
+                (3, "parse".into(), Some("parse_config".into())),
+                (4, "config".into(), None),
+                (5, ")".into(), None),
+            ]
+        );
+
+        // the identifiers of the queries are emitted as a whole
+        let s = code.normalize("x.parseConfig");
+        let query: Vec<_> = code
+            .tokenize(&s, true)
+            .map(|t| (t.pos, t.token.into_owned(), t.mergeable))
+            .collect();
+        assert_eq!(
+            query,
+            [
+                (0, "x".into(), true),
+                (1, ".".into(), true),
+                (2, "parseconfig".into(), false),
+                (3, "config".into(), true),
+            ]
+        );
+    }
+
+    #[cfg(feature = "stemming")]
+    #[test]
+    fn stemming() {
+        use super::{Language, StemMode};
+        use std::borrow::Cow;
+
+        let replace = Analyzer::new().with_stemmer(Language::English, StemMode::Replace);
+        assert_eq!(replace.index_token("running"), (Cow::from("run"), None));
+        assert_eq!(replace.query_token("runs", true), "run");
+        assert!(replace.uses_common_tokens(false));
+
+        let stack = Analyzer::new().with_stemmer(Language::English, StemMode::Stack);
+        assert_eq!(
+            stack.index_token("running"),
+            (Cow::from("running"), Some(Cow::from("run")))
+        );
+        assert_eq!(stack.index_token("run"), (Cow::from("run"), None));
+        assert_eq!(stack.query_token("runs", false), "run");
+        assert_eq!(stack.query_token("runs", true), "runs");
+        assert!(!stack.uses_common_tokens(false));
+        assert!(stack.uses_common_tokens(true));
+
+        let stems: Vec<_> = ["cats", "ponies", "generously", "skies", "news"]
+            .into_iter()
+            .map(|t| replace.index_token(t).0.into_owned())
+            .collect();
+        assert_eq!(stems, ["cat", "poni", "generous", "sky", "news"]);
+    }
+
+    #[cfg(not(feature = "stemming"))]
+    #[test]
+    fn stemming_requires_the_feature() {
+        use super::{Language, StemMode};
+        use crate::{DbError, Indexer, Searcher, test_utils::TempDir};
+
+        let analyzer = Analyzer {
+            stemmer: Some((Language::English, StemMode::Stack)),
+            ..Analyzer::default()
+        };
+        assert!(matches!(
+            analyzer.check(),
+            Err(DbError::FeatureNotEnabled(_))
+        ));
+
+        // the index is written, but it can't be opened
+        let dir = TempDir::new("stemming_feature");
+        let indexer = Indexer::new(None, None).with_analyzer(analyzer);
+        assert!(indexer.index(vec![("running", 0)], dir.path()).is_err());
+        assert!(matches!(
+            Searcher::<u32>::new(dir.path()),
+            Err(DbError::FeatureNotEnabled(_))
+        ));
+    }
+}
@@ -0,0 +1,306 @@
+//! Porter2 (Snowball) English stemmer.
+//!
+//! <https://snowballstem.org/algorithms/english/stemmer.html>
+
+use std::borrow::Cow;
+
+/// Words with an irregular stem.
+const EXCEPTIONS: &[(&str, &str)] = &[
+    ("skis", "ski"),
+    ("skies", "sky"),
+    ("dying", "die"),
+    ("lying", "lie"),
+    ("tying", "tie"),
+    ("idly", "idl"),
+    ("gently", "gentl"),
+    ("ugly", "ugli"),
+    ("early", "earli"),
+    ("only", "onli"),
+    ("singly", "singl"),
+    ("sky", "sky"),
+    ("news", "news"),
+    ("howe", "howe"),
+    ("atlas", "atlas"),
+    ("cosmos", "cosmos"),
+    ("bias", "bias"),
+    ("andes", "andes"),
+];
+
+/// Words that are left as they are after step 1a.
+const EXCEPTIONS_1A: &[&[u8]] = &[
+    b"inning", b"outing", b"canning", b"herring", b"earring", b"proceed", b"exceed", b"succeed",
+];
+
+const STEP_1B: &[&[u8]] = &[b"eedly", b"ingly", b"edly", b"eed", b"ing", b"ed"];
+
+const STEP_2: &[(&[u8], &[u8])] = &[
+    (b"ization", b"ize"),
+    (b"ational", b"ate"),
+    (b"fulness", b"ful"),
+    (b"ousness", b"ous"),
+    (b"iveness", b"ive"),
+    (b"tional", b"tion"),
+    (b"biliti", b"ble"),
+    (b"lessli", b"less"),
+    (b"entli", b"ent"),
+    (b"ation", b"ate"),
+    (b"alism", b"al"),
+    (b"aliti", b"al"),
+    (b"ousli", b"ous"),
+    (b"iviti", b"ive"),
+    (b"fulli", b"ful"),
+    (b"enci", b"ence"),
+    (b"anci", b"ance"),
+    (b"abli", b"able"),
+    (b"izer", b"ize"),
+    (b"ator", b"ate"),
+    (b"alli", b"al"),
+    (b"bli", b"ble"),
+    (b"ogi", b"og"),
+    (b"li", b""),
+];
+
+const STEP_3: &[(&[u8], &[u8])] = &[
+    (b"ational", b"ate"),
+    (b"tional", b"tion"),
+    (b"alize", b"al"),
+    (b"icate", b"ic"),
+    (b"iciti", b"ic"),
+    (b"ative", b""),
+    (b"ical", b"ic"),
+    (b"ness", b""),
+    (b"ful", b""),
+];
+
+const STEP_4: &[&[u8]] = &[
+    b"ement", b"ance", b"ence", b"able", b"ible", b"ment", b"ant", b"ent", b"ism", b"ate", b"iti",
+    b"ous", b"ive", b"ize", b"ion", b"al", b"er", b"ic",
+];
+
+#[inline(always)]
+fn is_vowel(c: u8) -> bool {
+    matches!(c, b'a' | b'e' | b'i' | b'o' | b'u' | b'y')
+}
+
+#[inline(always)]
+fn is_double(w: &[u8]) -> bool {
+    match w {
+        [.., a, b] => {
+            a == b
+                && matches!(
+                    a,
+                    b'b' | b'd' | b'f' | b'g' | b'm' | b'n' | b'p' | b'r' | b't'
+                )
+        }
+        _ => false,
+    }
+}
+
+#[inline(always)]
+fn is_li_ending(c: u8) -> bool {
+    matches!(
+        c,
+        b'c' | b'd' | b'e' | b'g' | b'h' | b'k' | b'm' | b'n' | b'r' | b't'
+    )
+}
+
+/// Returns `true` if `w` ends with a short syllable.
+fn ends_with_short_syllable(w: &[u8]) -> bool {
+    match w {
+        [a, b] => is_vowel(*a) && !is_vowel(*b),
+        [.., a, b, c] => {
+            !is_vowel(*a) && is_vowel(*b) && !is_vowel(*c) && !matches!(c, b'w' | b'x' | b'Y')
+        }
+        _ => false,
+    }
+}
+
+/// Begining of the region after the first non-vowel
+/// following a vowel, starting at `start`.
+fn region(w: &[u8], start: usize) -> usize {
+    (start + 1..w.len())
+        .find(|i| !is_vowel(w[*i]) && is_vowel(w[*i - 1]))
+        .map(|i| i + 1)
+        .unwrap_or(w.len())
+}
+
+/// Longest suffix of `w` in `suffixes`, they have to be sorted by length.
+fn longest<'a>(w: &[u8], suffixes: &[&'a [u8]]) -> Option<&'a [u8]> {
+    suffixes.iter().find(|s| w.ends_with(s)).copied()
+}
+
+/// Longest suffix of `w` in `suffixes` and its replacement,
+/// they have to be sorted by length.
+fn longest_replacement<'a>(
+    w: &[u8],
+    suffixes: &[(&'a [u8], &'a [u8])],
+) -> Option<(&'a [u8], &'a [u8])> {
+    suffixes.iter().find(|(s, _)| w.ends_with(s)).copied()
+}
+
+/// Replaces `suffix` by `with`, if the suffix is after `r`.
+fn replace_in(w: &mut Vec<u8>, suffix: &[u8], with: &[u8], r: usize) {
+    let begin = w.len() - suffix.len();
+    if begin >= r {
+        w.truncate(begin);
+        w.extend_from_slice(with);
+    }
+}
+
+/// Stems an English `word`, that is already lowercase.
+///
+/// Words with anything other than ASCII letters
+/// and apostrophes are kept as they are.
+pub(crate) fn stem(word: &str) -> Cow<'_, str> {
+    if word.len() <= 2 || !word.bytes().all(|c| c.is_ascii_lowercase() || c == b'\'') {
+        return Cow::Borrowed(word);
+    }
+
+    if let Some((_, stem)) = EXCEPTIONS.iter().find(|(w, _)| *w == word) {
+        return match *stem == word {
+            true => Cow::Borrowed(word),
+            false => Cow::Owned(stem.to_string()),
+        };
+    }
+
+    let mut w = word.as_bytes().to_vec();
+    if w[0] == b'\'' {
+        w.remove(0);
+    }
+    if w.is_empty() {
+        return Cow::Borrowed(word);
+    }
+
+    // y's that behave like consonants
+    if w[0] == b'y' {
+        w[0] = b'Y';
+    }
+    for i in 1..w.len() {
+        if w[i] == b'y' && is_vowel(w[i - 1]) {
+            w[i] = b'Y';
+        }
+    }
+
+    let r1 = [b"gener".as_slice(), b"commun", b"arsen"]
+        .iter()
+        .find(|prefix| w.starts_with(prefix))
+        .map(|prefix| prefix.len())
+        .unwrap_or_else(|| region(&w, 0));
+    let r2 = region(&w, r1);
+
+    // step 0
+    for suffix in [b"'s'".as_slice(), b"'s", b"'"] {
+        if w.ends_with(suffix) {
+            w.truncate(w.len() - suffix.len());
+            break;
+        }
+    }
+
+    // step 1a
+    if w.ends_with(b"sses") {
+        w.truncate(w.len() - 2);
+    } else if w.ends_with(b"ied") || w.ends_with(b"ies") {
+        w.truncate(w.len() - 3);
+        match w.len() > 1 {
+            true => w.push(b'i'),
+            false => w.extend_from_slice(b"ie"),
+        }
+    } else if w.ends_with(b"s")
+        && !w.ends_with(b"us")
+        && !w.ends_with(b"ss")
+        && w.len() >= 2
+        && w[..w.len() - 2].iter().any(|c| is_vowel(*c))
+    {
+        w.pop();
+    }
+
+    if EXCEPTIONS_1A.contains(&w.as_slice()) {
+        return finish(word, w);
+    }
+
+    // step 1b
+    if let Some(suffix) = longest(&w, STEP_1B) {
+        if matches!(suffix, b"eed" | b"eedly") {
+            replace_in(&mut w, suffix, b"ee", r1);
+        } else {
+            let begin = w.len() - suffix.len();
+            if w[..begin].iter().any(|c| is_vowel(*c)) {
+                w.truncate(begin);
+                if w.ends_with(b"at") || w.ends_with(b"bl") || w.ends_with(b"iz") {
+                    w.push(b'e');
+                } else if is_double(&w) {
+                    w.pop();
+                } else if r1 >= w.len() && ends_with_short_syllable(&w) {
+                    w.push(b'e');
+                }
+            }
+        }
+    }
+
+    // step 1c
+    let n = w.len();
+    if n > 2 && matches!(w[n - 1], b'y' | b'Y') && !is_vowel(w[n - 2]) {
+        w[n - 1] = b'i';
+    }
+
+    // step 2
+    if let Some((suffix, with)) = longest_replacement(&w, STEP_2) {
+        let begin = w.len() - suffix.len();
+        let allowed = match suffix {
+            b"ogi" => begin > 0 && w[begin - 1] == b'l',
+            b"li" => begin > 0 && is_li_ending(w[begin - 1]),
+            _ => true,
+        };
+        if allowed {
+            replace_in(&mut w, suffix, with, r1);
+        }
+    }
+
+    // step 3
+    if let Some((suffix, with)) = longest_replacement(&w, STEP_3) {
+        let r = if matches!(suffix, b"ative") { r2 } else { r1 };
+        replace_in(&mut w, suffix, with, r);
+    }
+
+    // step 4
+    if let Some(suffix) = longest(&w, STEP_4) {
+        let begin = w.len() - suffix.len();
+        let allowed = match suffix {
+            b"ion" => begin > 0 && matches!(w[begin - 1], b's' | b't'),
+            _ => true,
+        };
+        if allowed {
+            replace_in(&mut w, suffix, b"", r2);
+        }
+    }
+
+    // step 5
+    let n = w.len();
+    match w.last() {
+        Some(b'e') if n > r2 || (n > r1 && !ends_with_short_syllable(&w[..n - 1])) => {
+            w.pop();
+        }
+        Some(b'l') if n > r2 && n >= 2 && w[n - 2] == b'l' => {
+            w.pop();
+        }
+        _ => {}
+    }
+
+    finish(word, w)
+}
+
+/// Turns the consonant y's back into lowercase and
+/// returns [Cow::Borrowed] if the stem is the `word` itself.
+fn finish(word: &str, mut w: Vec<u8>) -> Cow<'_, str> {
+    for c in w.iter_mut() {
+        if *c == b'Y' {
+            *c = b'y';
+        }
+    }
+
+    if w == word.as_bytes() {
+        return Cow::Borrowed(word);
+    }
+    // this can't fail, `w` only has ASCII letters and apostrophes
+    Cow::Owned(String::from_utf8(w).unwrap())
+}
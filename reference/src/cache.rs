@@ -0,0 +1,326 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering::Relaxed},
+    },
+};
+
+use gxhash::{HashMap as GxHashMap, HashMapExt};
+
+use crate::IntersectionAlgorithm;
+
+/// Key of the cache, the id of the index, the normalized tokens
+/// of the query, how they were searched and the intersection
+/// algorithm used.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    index_id: u64,
+    tokens: Box<str>,
+    /// If the query was searched with [crate::SearchOptions::with_exact].
+    exact: bool,
+    /// If the tokens of the query could be merged.
+    merge: bool,
+    algorithm: IntersectionAlgorithm,
+}
+
+impl CacheKey {
+    pub(crate) fn new(
+        index_id: u64,
+        tokens: &str,
+        exact: bool,
+        merge: bool,
+        algorithm: IntersectionAlgorithm,
+    ) -> Self {
+        Self {
+            index_id,
+            tokens: tokens.into(),
+            exact,
+            merge,
+            algorithm,
+        }
+    }
+
+    /// Approximate number of bytes used by an entry with this key.
+    fn size_bytes(&self, doc_ids: &[u32]) -> usize {
+        // the key is stored twice, once in the map and once in the lru
+        2 * (std::mem::size_of::<Self>() + self.tokens.len())
+            + std::mem::size_of::<CacheEntry>()
+            + std::mem::size_of_val(doc_ids)
+    }
+}
+
+struct CacheEntry {
+    doc_ids: Vec<u32>,
+    /// Last time this entry was used.
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    map: GxHashMap<CacheKey, CacheEntry>,
+    /// Keys ordered by the last time they were used.
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    size_bytes: usize,
+}
+
+/// Bounded LRU cache of search results.
+///
+/// Results are keyed by the id of the index, the normalized tokens of
+/// the query, how they were searched and the [IntersectionAlgorithm] used,
+/// so different queries that normalize to the same tokens share the same
+/// entry. Only successful searches are cached.
+///
+/// The cache can be shared between multiple [crate::Searcher]s (see
+/// [crate::Searcher::with_cache]), even of different indexes. The id
+/// changes every time an index is (re)generated, so the results of an
+/// old index are never returned and are eventually evicted. Indexes
+/// generated before the id existed don't have one, so their results
+/// are never cached.
+pub struct SearchCache {
+    capacity_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SearchCache {
+    /// Creates a new cache that holds at most `capacity_bytes`
+    /// (approximately) of results.
+    pub fn new(capacity_bytes: usize) -> Self {
+        Self {
+            capacity_bytes,
+            inner: Mutex::new(Inner {
+                map: GxHashMap::new(),
+                ..Default::default()
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // the cache is always left in a consistent state,
+        // so it's fine to ignore the poisoning
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Maximum number of bytes (approximately) used by the cache.
+    pub fn capacity_bytes(&self) -> usize {
+        self.capacity_bytes
+    }
+
+    /// Number of bytes (approximately) currently used by the cache.
+    pub fn size_bytes(&self) -> usize {
+        self.lock().size_bytes
+    }
+
+    /// Number of cached results.
+    pub fn len(&self) -> usize {
+        self.lock().map.len()
+    }
+
+    /// Returns `true` if there are no cached results.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of searches answered by the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Relaxed)
+    }
+
+    /// Number of searches that were not in the cache.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Relaxed)
+    }
+
+    /// Removes all of the cached results.
+    ///
+    /// The hit and miss counters are kept.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.map.clear();
+        inner.lru.clear();
+        inner.size_bytes = 0;
+    }
+
+    /// Gets the cached result for `key`.
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<Vec<u32>> {
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        let Some(entry) = inner.map.get_mut(key) else {
+            self.misses.fetch_add(1, Relaxed);
+            return None;
+        };
+        let old_tick = entry.tick;
+        entry.tick = tick;
+        let doc_ids = entry.doc_ids.clone();
+
+        if let Some(key) = inner.lru.remove(&old_tick) {
+            inner.lru.insert(tick, key);
+        }
+
+        self.hits.fetch_add(1, Relaxed);
+        Some(doc_ids)
+    }
+
+    /// Caches the result of `key`, evicting the least recently
+    /// used results if needed.
+    pub(crate) fn insert(&self, key: CacheKey, doc_ids: &[u32]) {
+        let size_bytes = key.size_bytes(doc_ids);
+        if size_bytes > self.capacity_bytes {
+            return;
+        }
+
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        if let Some(old) = inner.map.remove(&key) {
+            inner.lru.remove(&old.tick);
+            inner.size_bytes -= key.size_bytes(&old.doc_ids);
+        }
+
+        while inner.size_bytes + size_bytes > self.capacity_bytes {
+            let Some((_, key)) = inner.lru.pop_first() else {
+                break;
+            };
+            if let Some(old) = inner.map.remove(&key) {
+                inner.size_bytes -= key.size_bytes(&old.doc_ids);
+            }
+        }
+
+        inner.lru.insert(tick, key.clone());
+        inner.map.insert(
+            key,
+            CacheEntry {
+                doc_ids: doc_ids.to_vec(),
+                tick,
+            },
+        );
+        inner.size_bytes += size_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheKey, SearchCache};
+    use crate::{Indexer, IntersectionAlgorithm, NaiveIntersect, test_utils::TempDir};
+
+    fn key(tokens: &str) -> CacheKey {
+        CacheKey::new(1, tokens, false, true, IntersectionAlgorithm::Simd)
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let doc_ids = [1, 2, 3];
+        // the keys have the same length, so the entries have the same size
+        let size_bytes = key("a").size_bytes(&doc_ids);
+        let cache = SearchCache::new(2 * size_bytes);
+
+        cache.insert(key("a"), &doc_ids);
+        cache.insert(key("b"), &doc_ids);
+        assert_eq!(cache.get(&key("a")), Some(doc_ids.to_vec()));
+
+        cache.insert(key("c"), &doc_ids);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("b")), None);
+        assert_eq!(cache.get(&key("a")), Some(doc_ids.to_vec()));
+        assert_eq!(cache.get(&key("c")), Some(doc_ids.to_vec()));
+    }
+
+    #[test]
+    fn evicts_by_size() {
+        let small = [1];
+        let big: Vec<u32> = (0..64).collect();
+        let capacity_bytes = key("big").size_bytes(&big) + key("a").size_bytes(&small);
+        let cache = SearchCache::new(capacity_bytes);
+
+        for tokens in ["a", "b", "c", "d"] {
+            cache.insert(key(tokens), &small);
+        }
+        let size_bytes = cache.size_bytes();
+        assert_eq!(size_bytes, 4 * key("a").size_bytes(&small));
+
+        // only the most recent small entry fits with the big one
+        cache.insert(key("big"), &big);
+        assert_eq!(cache.size_bytes(), capacity_bytes);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("d")), Some(small.to_vec()));
+        assert_eq!(cache.get(&key("big")), Some(big.clone()));
+
+        // replacing an entry doesn't count it twice
+        cache.insert(key("big"), &big);
+        assert_eq!(cache.size_bytes(), capacity_bytes);
+
+        // entries bigger than the cache are never inserted
+        let huge: Vec<u32> = (0..1024).collect();
+        cache.insert(key("huge"), &huge);
+        assert_eq!(cache.get(&key("huge")), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = SearchCache::new(1024);
+        assert_eq!(cache.get(&key("a")), None);
+        cache.insert(key("a"), &[1]);
+        assert_eq!(cache.get(&key("a")), Some(vec![1]));
+        assert_eq!(cache.get(&key("a")), Some(vec![1]));
+        assert_eq!(cache.get(&key("b")), None);
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.size_bytes(), 0);
+        assert_eq!(cache.get(&key("a")), None);
+        assert_eq!((cache.hits(), cache.misses()), (2, 3));
+    }
+
+    #[test]
+    fn keys_include_how_the_tokens_were_searched() {
+        let cache = SearchCache::new(1024);
+        let keys = [
+            CacheKey::new(1, "a b", false, true, IntersectionAlgorithm::Simd),
+            CacheKey::new(2, "a b", false, true, IntersectionAlgorithm::Simd),
+            CacheKey::new(1, "a b", true, true, IntersectionAlgorithm::Simd),
+            CacheKey::new(1, "a b", false, false, IntersectionAlgorithm::Simd),
+            CacheKey::new(1, "a b", false, true, IntersectionAlgorithm::Naive),
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            cache.insert(key.clone(), &[i as u32]);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(cache.get(key), Some(vec![i as u32]));
+        }
+    }
+
+    #[test]
+    fn only_caches_successful_searches() {
+        let dir = TempDir::new("cache_successful");
+        let docs = vec![("look at my cat", 0), ("look at my dog", 1)];
+        let (searcher, _) = Indexer::new(None, None).index(docs, dir.path()).unwrap();
+        let cache = std::sync::Arc::new(SearchCache::new(1024));
+        let searcher = searcher.with_cache(cache.clone());
+
+        assert!(searcher.search::<NaiveIntersect>("my cat dog").0.is_err());
+        assert!(
+            searcher
+                .search::<NaiveIntersect>("look at my bird")
+                .0
+                .is_err()
+        );
+        assert!(cache.is_empty());
+
+        let r = searcher.search::<NaiveIntersect>("look at my").0.unwrap();
+        assert_eq!(r, vec![0, 1]);
+        assert_eq!(cache.len(), 1);
+        let r = searcher.search::<NaiveIntersect>("look at my").0.unwrap();
+        assert_eq!(r, vec![0, 1]);
+        assert_eq!(cache.hits(), 1);
+    }
+}
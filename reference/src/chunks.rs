@@ -0,0 +1,106 @@
+use crate::{doc_set::DocSet, roaringish::MAX_VALUE};
+
+/// Number of positions shared by consecutive chunks, phrases with up
+/// to this many tokens can match across the boundary of the chunks.
+pub const CHUNK_OVERLAP: u32 = 1 << 12;
+
+/// Number of positions between the start of consecutive chunks.
+pub const CHUNK_STEP: u32 = MAX_VALUE - CHUNK_OVERLAP;
+
+/// Documents longer than [MAX_VALUE] positions are indexed as multiple
+/// overlapping chunks, the first chunk uses the ID of the document and
+/// the others use the IDs that follow it.
+///
+/// The chunks are mapped back to the ID of their documents,
+/// so they are never returned by a search.
+#[derive(Debug, Default)]
+pub struct Chunks {
+    /// ID of each chunk and of its document, sorted by the ID of the chunk.
+    ids: Vec<[u32; 2]>,
+}
+
+impl From<Vec<[u32; 2]>> for Chunks {
+    fn from(ids: Vec<[u32; 2]>) -> Self {
+        Self { ids }
+    }
+}
+
+impl Chunks {
+    /// Adds the chunk `chunk_id` of `doc_id`, the chunks
+    /// have to be added in increasing order.
+    pub fn push(&mut self, chunk_id: u32, doc_id: u32) {
+        self.ids.push([chunk_id, doc_id]);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn ids(&self) -> &Vec<[u32; 2]> {
+        &self.ids
+    }
+
+    /// Replaces the IDs of the chunks in the sorted `doc_ids`
+    /// by the IDs of their documents.
+    pub fn resolve(&self, mut doc_ids: Vec<u32>) -> Vec<u32> {
+        if self.is_empty() {
+            return doc_ids;
+        }
+
+        let mut found = false;
+        for doc_id in doc_ids.iter_mut() {
+            if let Ok(i) = self
+                .ids
+                .binary_search_by_key(doc_id, |[chunk_id, _]| *chunk_id)
+            {
+                *doc_id = self.ids[i][1];
+                found = true;
+            }
+        }
+
+        // the chunks follow their documents, so `doc_ids` is still sorted
+        if found {
+            doc_ids.dedup();
+        }
+        doc_ids
+    }
+
+    /// Adds the IDs of the chunks of the documents in `doc_set`.
+    pub fn expand(&self, doc_set: &DocSet) -> DocSet {
+        let mut doc_ids = Vec::with_capacity(doc_set.len());
+        for doc_id in doc_set.iter().copied() {
+            doc_ids.push(doc_id);
+
+            // the chunks follow their documents, so
+            // they are also sorted by the ID of the document
+            let i = self.ids.partition_point(|[_, id]| *id < doc_id);
+            let chunk_ids = self.ids[i..]
+                .iter()
+                .take_while(|[_, id]| *id == doc_id)
+                .map(|[chunk_id, _]| *chunk_id);
+            doc_ids.extend(chunk_ids);
+        }
+        DocSet::from(doc_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chunks;
+    use crate::doc_set::DocSet;
+
+    #[test]
+    fn resolve_and_expand() {
+        // the document 1 has the chunks 2 and 3 and the document 5 has the chunk 6
+        let chunks = Chunks::from(vec![[2, 1], [3, 1], [6, 5]]);
+
+        assert_eq!(chunks.resolve(vec![0, 1, 3, 4, 6]), [0, 1, 4, 5]);
+        assert_eq!(chunks.resolve(vec![2, 3]), [1]);
+
+        let expand = |doc_ids: Vec<u32>| chunks.expand(&DocSet::from(doc_ids)).to_vec();
+        assert_eq!(expand(vec![]), Vec::<u32>::new());
+        assert_eq!(expand(vec![0, 4]), [0, 4]);
+        assert_eq!(expand(vec![1, 5]), [1, 2, 3, 5, 6]);
+        assert_eq!(expand(vec![0, 1, 4, 5, 7]), [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+}
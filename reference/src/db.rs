@@ -2,7 +2,8 @@ use bumpalo::Bump;
 use gxhash::{HashMap as GxHashMap, HashMapExt};
 use heed::{
     Database, DatabaseFlags, Env, EnvFlags, EnvOpenOptions, PutFlags, RoTxn, RwTxn, Unspecified,
-    types::Str,
+    byteorder::NativeEndian,
+    types::{Bytes, Str, U64},
 };
 use memmap2::{Mmap, MmapMut};
 use rkyv::{
@@ -26,48 +27,145 @@ use std::{
     ops::Index,
     path::Path,
     sync::atomic::Ordering::Relaxed,
+    time::Duration,
 };
 
 use crate::{
-    BorrowRoaringishPacked, Intersection, RoaringishPacked,
+    Analyzer, BorrowRoaringishPacked, Intersection, IntersectionAlgorithm, RoaringishPacked,
+    SearchCache,
+    cache::CacheKey,
+    chunks::Chunks,
     codecs::{NativeU32, ZeroCopyCodec},
+    doc_set::DocSet,
     error::{DbError, GetDocumentError, SearchError},
-    normalize,
+    explain::{Explain, ExplainStep, ExplainToken},
+    indexer::TokenizedBatch,
+    options::SearchOptions,
+    phrases::Phrases,
     roaringish::{Aligned, ArchivedBorrowRoaringishPacked, RoaringishPackedKind, Unaligned},
     stats::Stats,
-    tokenize,
+    synonyms::SynonymMap,
 };
 
+/// Separates the alternatives of a position of a query expanded by
+/// the synonyms, the tokens themselves never contain whitespace.
+const ALTERNATIVES_SEPARATOR: &str = "\n";
+
 struct Tokens {
     tokens: String,
     positions: Vec<(usize, usize)>,
+    /// Position of each token in the query, the analyzer can leave gaps.
+    query_positions: Vec<u32>,
+    /// If each token can be merged with its neighbours.
+    mergeable: Vec<bool>,
+    /// If the query can use the merged common tokens.
+    merge: bool,
 }
 
 impl Tokens {
-    fn new(q: &str) -> Self {
-        let q = normalize(q);
-        let mut start = 0;
-        let mut tokens = String::with_capacity(q.len() + 1);
-        let mut positions = Vec::with_capacity(q.len() + 1);
-
-        for token in tokenize(&q) {
-            tokens.push_str(token);
-            tokens.push(' ');
-
-            let b = start;
-            let e = b + token.len();
-            start = e + 1;
-            positions.push((b, e));
+    fn new(q: &str, analyzer: &Analyzer, exact: bool) -> Self {
+        let q = analyzer.normalize(q);
+        let mut me = Self::with_capacity(q.len() + 1, analyzer.uses_common_tokens(exact));
+
+        for token in analyzer.tokenize(&q, true) {
+            let mergeable = token.mergeable;
+            me.push(
+                &analyzer.query_token(&token.token, exact),
+                token.pos,
+                mergeable,
+            );
+        }
+
+        me
+    }
+
+    /// Same as [Self::new], but expands the query with the `synonyms`
+    /// into all of the queries that need to be searched.
+    ///
+    /// Positions with multiple alternatives are separated by
+    /// [ALTERNATIVES_SEPARATOR] and can't be merged, neither
+    /// can the tokens that the analyzer doesn't merge.
+    fn expand(
+        q: &str,
+        analyzer: &Analyzer,
+        exact: bool,
+        synonyms: Option<&SynonymMap>,
+    ) -> Result<Vec<Self>, SearchError> {
+        let Some(synonyms) = synonyms.filter(|synonyms| !synonyms.is_empty()) else {
+            return Ok(vec![Self::new(q, analyzer, exact)]);
+        };
+
+        let q = analyzer.normalize(q);
+        let merge = analyzer.uses_common_tokens(exact);
+        let mut unmergeable = HashSet::new();
+        let (tokens, positions): (Vec<_>, Vec<_>) = analyzer
+            .tokenize(&q, true)
+            .map(|token| {
+                if !token.mergeable {
+                    unmergeable.insert(token.token.clone());
+                }
+                (token.token, token.pos)
+            })
+            .unzip();
+        let tokens: Vec<&str> = tokens.iter().map(|t| t.as_ref()).collect();
+
+        let expansions = synonyms.expand(&tokens, &positions)?;
+        let expansions = expansions
+            .into_iter()
+            .map(|expansion| {
+                let mut me = Self::with_capacity(q.len() + 1, merge);
+                for (pos, alternatives) in expansion {
+                    let mergeable = match alternatives.as_slice() {
+                        [token] => !unmergeable.contains(token.as_ref()),
+                        _ => false,
+                    };
+                    let mut alternatives: Vec<_> = alternatives
+                        .iter()
+                        .map(|token| analyzer.query_token(token, exact))
+                        .collect();
+                    alternatives.sort_unstable();
+                    alternatives.dedup();
+
+                    let token = alternatives.join(ALTERNATIVES_SEPARATOR);
+                    me.push(&token, pos, mergeable && alternatives.len() == 1);
+                }
+                me
+            })
+            .collect();
+        Ok(expansions)
+    }
+
+    fn with_capacity(capacity: usize, merge: bool) -> Self {
+        Self {
+            tokens: String::with_capacity(capacity),
+            positions: Vec::with_capacity(capacity),
+            query_positions: Vec::with_capacity(capacity),
+            mergeable: Vec::with_capacity(capacity),
+            merge,
         }
-        tokens.pop();
+    }
 
-        Self { tokens, positions }
+    /// Pushes the `token` at the position `pos` of the query, the tokens
+    /// are separated by a space and by one more for each position of a gap,
+    /// so queries with different gaps also have different tokens.
+    fn push(&mut self, token: &str, pos: u32, mergeable: bool) {
+        if let Some(last) = self.query_positions.last() {
+            let gap = pos.saturating_sub(last + 1) as usize;
+            self.tokens.extend(std::iter::repeat_n(' ', gap + 1));
+        }
+        let b = self.tokens.len();
+        self.tokens.push_str(token);
+        self.positions.push((b, self.tokens.len()));
+        self.query_positions.push(pos);
+        self.mergeable.push(mergeable);
     }
 
     fn as_ref(&self) -> RefTokens {
         RefTokens {
             tokens: &self.tokens,
             positions: &self.positions,
+            query_positions: &self.query_positions,
+            mergeable: &self.mergeable,
         }
     }
 }
@@ -76,6 +174,8 @@ impl Tokens {
 struct RefTokens<'a> {
     tokens: &'a str,
     positions: &'a [(usize, usize)],
+    query_positions: &'a [u32],
+    mergeable: &'a [bool],
 }
 
 impl RefTokens<'_> {
@@ -87,8 +187,8 @@ impl RefTokens<'_> {
         self.len() == 0
     }
 
-    fn reserve_len(&self) -> usize {
-        let n = MAX_WINDOW_LEN.get();
+    fn reserve_len(&self, window_len: NonZero<usize>) -> usize {
+        let n = window_len.get();
         let l = self.len();
         n * (l.max(n) - n + 1) + ((n - 1) * n) / 2
     }
@@ -103,6 +203,35 @@ impl RefTokens<'_> {
         (0..self.positions.len()).map(|i| Self {
             tokens: self.tokens,
             positions: &self.positions[i..i + 1],
+            query_positions: &self.query_positions[i..i + 1],
+            mergeable: &self.mergeable[i..i + 1],
+        })
+    }
+
+    /// Position of the first token in the query.
+    fn query_position(&self) -> u32 {
+        self.query_positions.first().copied().unwrap_or(0)
+    }
+
+    /// Splits the tokens into the runs of consecutive tokens that can be
+    /// merged, each token that can't be merged is on its own.
+    fn segments(&self) -> impl Iterator<Item = Self> {
+        let mut rem = *self;
+        std::iter::from_fn(move || {
+            let len = match rem.mergeable.first()? {
+                true => {
+                    1 + rem
+                        .mergeable
+                        .windows(2)
+                        .zip(rem.query_positions.windows(2))
+                        .take_while(|(m, p)| m[1] && p[1] == p[0] + 1)
+                        .count()
+                }
+                false => 1,
+            };
+            let (segment, r) = rem.split_at(len);
+            rem = r;
+            Some(segment)
         })
     }
 
@@ -125,14 +254,20 @@ impl RefTokens<'_> {
 
     fn split_at(&self, i: usize) -> (Self, Self) {
         let (l, r) = self.positions.split_at(i);
+        let (ql, qr) = self.query_positions.split_at(i);
+        let (ml, mr) = self.mergeable.split_at(i);
         (
             Self {
                 tokens: self.tokens,
                 positions: l,
+                query_positions: ql,
+                mergeable: ml,
             },
             Self {
                 tokens: self.tokens,
                 positions: r,
+                query_positions: qr,
+                mergeable: mr,
             },
         )
     }
@@ -202,15 +337,118 @@ impl<'a, 'alloc> Iterator for RefTokenLinkedListIter<'a, 'alloc> {
 #[derive(Archive, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct BorrowStr<'a>(#[rkyv(with = InlineAsBox)] &'a str);
 
+/// Single intersection performed by [DB::intersect_final_tokens].
+///
+/// Ranges are indices (`begin..end`) into the final tokens.
+#[derive(Clone, Copy, Debug)]
+struct IntersectStep {
+    lhs: (usize, usize),
+    rhs: (usize, usize),
+    lhs_len: u32,
+    lhs_packed_len: usize,
+    rhs_packed_len: usize,
+    algorithms: Option<[IntersectionAlgorithm; 2]>,
+    result_len: usize,
+    elapsed: Duration,
+}
+
+/// Pair of tokens and the distance between them.
+type TokenPair = (Box<str>, Box<str>, u32);
+
+/// Intersections of the first pair of tokens that are shared
+/// between multiple queries in [DB::search_many].
+#[derive(Default)]
+struct SharedIntersections {
+    /// Maps the pair of tokens and the distance between them to the number
+    /// of queries that still need the intersection and the intersection
+    /// itself (once computed).
+    pairs: GxHashMap<TokenPair, (usize, Option<RoaringishPacked>)>,
+}
+
+impl SharedIntersections {
+    /// Registers that a query will start by intersecting `lhs` with `rhs`.
+    fn add(&mut self, lhs: &RefTokens, rhs: &RefTokens) {
+        let key = Self::key(lhs, rhs);
+        self.pairs.entry(key).or_insert((0, None)).0 += 1;
+    }
+
+    fn key(lhs: &RefTokens, rhs: &RefTokens) -> TokenPair {
+        let lhs_len = rhs.query_position() - lhs.query_position();
+        (lhs.tokens().into(), rhs.tokens().into(), lhs_len)
+    }
+
+    /// Intersects `lhs` with `rhs` or reuses the result from a previous query.
+    ///
+    /// The result is only kept while there are queries that still need it.
+    fn intersect<I: Intersection>(
+        &mut self,
+        t_lhs: &RefTokens,
+        t_rhs: &RefTokens,
+        lhs: BorrowRoaringishPacked<'_, Aligned>,
+        rhs: BorrowRoaringishPacked<'_, Aligned>,
+        stats: &Stats,
+    ) -> (RoaringishPacked, Option<[IntersectionAlgorithm; 2]>) {
+        let key = Self::key(t_lhs, t_rhs);
+        let lhs_len = key.2;
+        let Entry::Occupied(mut e) = self.pairs.entry(key) else {
+            return lhs.intersect_with_algorithms::<I>(rhs, lhs_len, stats);
+        };
+
+        let (uses, packed) = e.get_mut();
+        *uses -= 1;
+        let r = match packed {
+            Some(packed) if *uses == 0 => (std::mem::take(packed), None),
+            Some(packed) => (packed.clone(), None),
+            None => {
+                let r = lhs.intersect_with_algorithms::<I>(rhs, lhs_len, stats);
+                if *uses > 0 {
+                    *packed = Some(r.0.clone());
+                }
+                r
+            }
+        };
+
+        if *uses == 0 {
+            e.remove();
+        }
+        r
+    }
+}
+
 mod db_constants {
     pub const DB_DOC_ID_TO_DOCUMENT: &str = "doc_id_to_document";
     pub const DB_TOKEN_TO_OFFSETS: &str = "token_to_offsets";
+    pub const DB_LONG_TOKEN_TO_OFFSETS: &str = "long_token_to_offsets";
     pub const KEY_COMMON_TOKENS: &str = "common_tokens";
+    pub const KEY_INDEX_ID: &str = "index_id";
+    pub const KEY_ANALYZER: &str = "analyzer";
+    pub const KEY_CHUNKS: &str = "chunks";
+    pub const KEY_WINDOW_LEN: &str = "window_len";
+    pub const KEY_PHRASES: &str = "phrases";
     pub const FILE_ROARINGISH_PACKED: &str = "roaringish_packed";
     pub const TEMP_FILE_TOKEN_TO_PACKED: &str = "temp_token_to_packed";
+    pub const TEMP_FILE_TOKENIZED_BATCH: &str = "temp_tokenized_batch";
 }
 
-pub const MAX_WINDOW_LEN: NonZero<usize> = unsafe { NonZero::new_unchecked(3) };
+/// Default maximum number of tokens merged into a single token.
+pub const DEFAULT_WINDOW_LEN: NonZero<usize> = NonZero::new(3).unwrap();
+
+/// Maximum length of the keys in LMDB.
+const MAX_KEY_LEN: usize = 511;
+
+/// Initial size of the LMDB map, it's grown before each write transaction
+/// by [DB::reserve_map_size] and doubled every time it's still full.
+const INITIAL_MAP_SIZE: usize = 1 << 26;
+
+/// The map is grown in multiples of this, so it's a multiple of the page size.
+const MAP_SIZE_GRANULARITY: usize = 1 << 16;
+
+/// Estimated bytes of the entry of a token and its offset in the map,
+/// the merged tokens are longer than the words.
+const TOKEN_ENTRY_LEN: usize = 64;
+
+/// Bytes of the entry of a document in the map, besides the document.
+const DOCUMENT_ENTRY_LEN: usize = 16;
 
 #[derive(Debug, Serialize, Archive)]
 struct Offset {
@@ -218,6 +456,25 @@ struct Offset {
     len: u64,
 }
 
+/// Token longer than [MAX_KEY_LEN], stored by its hash.
+#[derive(Debug, Serialize, Archive)]
+struct LongToken {
+    token: Box<str>,
+    offset: Offset,
+}
+
+/// Token as shown to the user, the alternatives
+/// of a position are separated by a `|`.
+fn display_token(token: &str) -> String {
+    token.replace(ALTERNATIVES_SEPARATOR, "|")
+}
+
+/// Hash used as the key of the tokens longer than [MAX_KEY_LEN].
+#[inline(always)]
+fn long_token_hash(token: &str) -> u64 {
+    fxhash::hash64(token)
+}
+
 /// Represents all types that can be stored in the database.
 ///
 /// This basically means that the type must be serializable by [rkyv].
@@ -239,6 +496,18 @@ pub struct DB<D: Document> {
     db_main: Database<Unspecified, Unspecified>,
     db_doc_id_to_document: Database<NativeU32, ZeroCopyCodec<D>>,
     db_token_to_offsets: Database<Str, ZeroCopyCodec<Offset>>,
+    /// Indexes created before the long tokens were supported don't have it.
+    db_long_token_to_offsets: Option<Database<U64<NativeEndian>, ZeroCopyCodec<Vec<LongToken>>>>,
+    analyzer: Analyzer,
+    chunks: Chunks,
+    /// Maximum number of tokens merged into a single token.
+    window_len: NonZero<usize>,
+    phrases: Phrases,
+    /// Id of the index, used as part of the key of the cached results.
+    ///
+    /// Indexes generated before the id existed don't have one,
+    /// so their results can't be cached.
+    index_id: Option<u64>,
 }
 
 unsafe impl<D: Document> Send for DB<D> {}
@@ -246,15 +515,15 @@ unsafe impl<D: Document> Send for DB<D> {}
 unsafe impl<D: Document> Sync for DB<D> {}
 
 impl<D: Document> DB<D> {
-    pub fn truncate<P: AsRef<Path>>(path: P, db_size: usize) -> Result<Self, DbError> {
+    pub fn truncate<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
         let path = path.as_ref();
         let _ = std::fs::remove_dir_all(path);
         std::fs::create_dir_all(path)?;
 
         let env = unsafe {
             EnvOpenOptions::new()
-                .max_dbs(2)
-                .map_size(db_size)
+                .max_dbs(3)
+                .map_size(INITIAL_MAP_SIZE)
                 .flags(EnvFlags::WRITE_MAP | EnvFlags::MAP_ASYNC)
                 .open(path)?
         };
@@ -273,6 +542,9 @@ impl<D: Document> DB<D> {
         let db_token_to_offsets =
             env.create_database(&mut wrtxn, Some(db_constants::DB_TOKEN_TO_OFFSETS))?;
 
+        let db_long_token_to_offsets =
+            env.create_database(&mut wrtxn, Some(db_constants::DB_LONG_TOKEN_TO_OFFSETS))?;
+
         wrtxn.commit()?;
 
         Ok(Self {
@@ -280,25 +552,77 @@ impl<D: Document> DB<D> {
             db_main,
             db_doc_id_to_document,
             db_token_to_offsets,
+            db_long_token_to_offsets: Some(db_long_token_to_offsets),
+            analyzer: Analyzer::default(),
+            chunks: Chunks::default(),
+            window_len: DEFAULT_WINDOW_LEN,
+            phrases: Phrases::default(),
+            index_id: None,
         })
     }
 
+    /// Serializes the `documents`, so the map can be grown
+    /// for them before [Self::write_doc_id_to_document].
+    pub fn serialize_documents(documents: &[D]) -> Result<Vec<AlignedVec>, DbError> {
+        documents
+            .iter()
+            .map(|document| Ok(rkyv::to_bytes::<rkyv::rancor::Error>(document)?))
+            .collect()
+    }
+
     pub fn write_doc_id_to_document(
         &self,
         rwtxn: &mut RwTxn,
         doc_ids: &[u32],
-        documents: &[D],
+        documents: &[AlignedVec],
     ) -> Result<(), DbError> {
         log::debug!("Writing documents");
         let b = std::time::Instant::now();
+        let db_doc_id_to_document = self.db_doc_id_to_document.remap_data_type::<Bytes>();
         for (doc_id, document) in doc_ids.iter().zip(documents.iter()) {
-            self.db_doc_id_to_document
-                .put_with_flags(rwtxn, PutFlags::APPEND, doc_id, document)?;
+            db_doc_id_to_document.put_with_flags(rwtxn, PutFlags::APPEND, doc_id, document)?;
         }
         log::debug!("Writing documents took {:?}", b.elapsed());
         Ok(())
     }
 
+    /// Grows the map, if needed, so `additional` bytes can be written
+    /// after the pages that are already used.
+    ///
+    /// The pages of the B-trees are only partially filled, so this reserves
+    /// twice as much, [Self::write_txn] still grows the map if it's full.
+    pub fn reserve_map_size(&self, additional: usize) -> Result<(), DbError> {
+        let used = self.env.non_free_pages_size()? as usize;
+        let map_size = (used + 2 * additional).next_multiple_of(MAP_SIZE_GRANULARITY);
+        if map_size <= self.env.info().map_size {
+            return Ok(());
+        }
+
+        log::debug!("Growing the map to {map_size} bytes");
+        // this is safe, the indexer doesn't have any other transaction
+        unsafe { self.env.resize(map_size)? };
+        Ok(())
+    }
+
+    /// Same as [Self::reserve_map_size], for the transaction
+    /// of [Self::generate_mmap_file] with `number_of_distinct_tokens`.
+    pub fn reserve_map_size_for_tokens(
+        &self,
+        number_of_distinct_tokens: u64,
+    ) -> Result<(), DbError> {
+        self.reserve_map_size(number_of_distinct_tokens as usize * TOKEN_ENTRY_LEN)
+    }
+
+    /// Same as [Self::reserve_map_size], for the `documents`
+    /// serialized by [Self::serialize_documents].
+    pub fn reserve_map_size_for_documents(&self, documents: &[AlignedVec]) -> Result<(), DbError> {
+        let len = documents
+            .iter()
+            .map(|document| document.len() + DOCUMENT_ENTRY_LEN)
+            .sum();
+        self.reserve_map_size(len)
+    }
+
     pub fn write_token_to_roaringish_packed(
         &self,
         token_to_token_id: &GxHashMap<Box<str>, u32>,
@@ -423,6 +747,7 @@ impl<D: Document> DB<D> {
             }
         }
 
+        let mut long_tokens: GxHashMap<u64, Vec<LongToken>> = GxHashMap::new();
         let mut heap = BinaryHeap::new();
         for (i, it) in iters.iter_mut().enumerate() {
             if let Some(token_to_packed) = it.next() {
@@ -468,12 +793,22 @@ impl<D: Document> DB<D> {
                 packed_kind = packed_kind.concat(next_to_merge_kind);
             }
 
-            if to_merge.token.0.len() > 511 {
+            let packed = packed_kind.as_bytes();
+            let offset = unsafe { write_to_mmap::<64>(&mut mmap, &mut mmap_offset, packed) };
+
+            // the token can't be used as a key, so it's stored by its hash
+            // alongside the token itself, to tell apart the collisions
+            if to_merge.token.0.len() > MAX_KEY_LEN {
+                long_tokens
+                    .entry(long_token_hash(&to_merge.token.0))
+                    .or_default()
+                    .push(LongToken {
+                        token: to_merge.token.0.as_ref().into(),
+                        offset,
+                    });
                 continue;
             }
 
-            let packed = packed_kind.as_bytes();
-            let offset = unsafe { write_to_mmap::<64>(&mut mmap, &mut mmap_offset, packed) };
             self.db_token_to_offsets.put_with_flags(
                 rwtxn,
                 PutFlags::APPEND,
@@ -486,17 +821,98 @@ impl<D: Document> DB<D> {
         drop(files_data);
         drop(files_mmaps);
 
+        log::debug!("Writing {} long token hashes", long_tokens.len());
+        if let Some(db_long_token_to_offsets) = self.db_long_token_to_offsets {
+            for (hash, long_tokens) in long_tokens.iter() {
+                db_long_token_to_offsets.put(rwtxn, hash, long_tokens)?;
+            }
+        }
+
         log::debug!("Finished merging roaringish packed files");
+        log::info!("Whole merging process took {:?}", b.elapsed());
+
+        Ok(())
+    }
+
+    /// Removes the files written by [Self::write_token_to_roaringish_packed],
+    /// after the transaction of [Self::generate_mmap_file] is committed.
+    pub fn remove_token_to_roaringish_packed_files(
+        &self,
+        number_of_batches: u32,
+    ) -> Result<(), DbError> {
         log::debug!("Removing old files");
         for i in 0..number_of_batches {
             let file_name = format!("{}_{i}", db_constants::TEMP_FILE_TOKEN_TO_PACKED);
             std::fs::remove_file(self.env.path().join(file_name))?;
         }
-        log::info!("Whole merging process took {:?}", b.elapsed());
+        Ok(())
+    }
 
+    /// Writes the tokenized documents of a batch, until the
+    /// common tokens are known and they can be merged.
+    pub fn write_tokenized_batch(
+        &self,
+        tokenized: &TokenizedBatch,
+        batch_id: u32,
+    ) -> Result<(), DbError> {
+        let file_name = format!("{}_{batch_id}", db_constants::TEMP_FILE_TOKENIZED_BATCH);
+        let file = IoWriter::new(BufWriter::new(
+            File::options()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(self.env.path().join(file_name))?,
+        ));
+        rkyv::api::high::to_bytes_in::<_, rkyv::rancor::Error>(tokenized, file)?;
         Ok(())
     }
 
+    /// Reads and removes the file written by [Self::write_tokenized_batch].
+    pub fn read_tokenized_batch(&self, batch_id: u32) -> Result<TokenizedBatch, DbError> {
+        let file_name = format!("{}_{batch_id}", db_constants::TEMP_FILE_TOKENIZED_BATCH);
+        let path = self.env.path().join(file_name);
+        let bytes = std::fs::read(&path)?;
+        // the file was written by `Self::write_tokenized_batch`
+        let tokenized = unsafe { rkyv::access_unchecked::<Archived<TokenizedBatch>>(&bytes) };
+        let tokenized = deserialize::<_, rkyv::rancor::Error>(tokenized)?;
+        std::fs::remove_file(path)?;
+        Ok(tokenized)
+    }
+
+    /// Runs `f` in a new write transaction and commits it.
+    ///
+    /// The map should already be big enough, see [Self::reserve_map_size].
+    /// If the map is full anyway the transaction is aborted, the map
+    /// is doubled and `f` is retried.
+    ///
+    /// So `f` has to be idempotent, only the writes to the transaction of
+    /// the last call are kept, so any other effect has to be the same when
+    /// it's repeated, e.g. [Self::generate_mmap_file] truncates the file
+    /// before writing it and it doesn't remove the files that it reads.
+    pub fn write_txn<T>(
+        &self,
+        mut f: impl FnMut(&mut RwTxn) -> Result<T, DbError>,
+    ) -> Result<T, DbError> {
+        loop {
+            let mut rwtxn = self.env.write_txn()?;
+            let r = f(&mut rwtxn).and_then(|r| {
+                rwtxn.commit()?;
+                Ok(r)
+            });
+
+            match r {
+                Err(DbError::LmdbError(heed::Error::Mdb(heed::MdbError::MapFull))) => {
+                    let map_size = self.env.info().map_size * 2;
+                    log::info!("Map is full, growing it to {map_size} bytes");
+                    // this is safe, the transaction was aborted and
+                    // the indexer doesn't have any other transaction
+                    unsafe { self.env.resize(map_size)? };
+                }
+                r => return r,
+            }
+        }
+    }
+
     fn read_common_tokens(
         rotxn: &RoTxn,
         db_main: Database<Unspecified, Unspecified>,
@@ -514,6 +930,131 @@ impl<D: Document> DB<D> {
         Ok(deserialize::<_, rkyv::rancor::Error>(k)?)
     }
 
+    /// Reads the analyzer of the index, indexes that
+    /// don't have one use the default analyzer.
+    fn read_analyzer(
+        rotxn: &RoTxn,
+        db_main: Database<Unspecified, Unspecified>,
+    ) -> Result<Analyzer, DbError> {
+        let analyzer = db_main
+            .remap_types::<Str, ZeroCopyCodec<Analyzer>>()
+            .get(rotxn, db_constants::KEY_ANALYZER)?;
+
+        match analyzer {
+            Some(analyzer) => Ok(deserialize::<_, rkyv::rancor::Error>(analyzer)?),
+            None => Ok(Analyzer::default()),
+        }
+    }
+
+    pub fn write_analyzer(&self, rwtxn: &mut RwTxn, analyzer: &Analyzer) -> Result<(), DbError> {
+        self.db_main
+            .remap_types::<Str, ZeroCopyCodec<Analyzer>>()
+            .put(rwtxn, db_constants::KEY_ANALYZER, analyzer)?;
+        Ok(())
+    }
+
+    /// Reads the chunks of the long documents, indexes
+    /// that don't have them return no chunks.
+    fn read_chunks(
+        rotxn: &RoTxn,
+        db_main: Database<Unspecified, Unspecified>,
+    ) -> Result<Chunks, DbError> {
+        let chunks = db_main
+            .remap_types::<Str, ZeroCopyCodec<Vec<[u32; 2]>>>()
+            .get(rotxn, db_constants::KEY_CHUNKS)?;
+
+        match chunks {
+            Some(chunks) => Ok(Chunks::from(deserialize::<
+                Vec<[u32; 2]>,
+                rkyv::rancor::Error,
+            >(chunks)?)),
+            None => Ok(Chunks::default()),
+        }
+    }
+
+    pub fn write_chunks(&self, rwtxn: &mut RwTxn, chunks: &Chunks) -> Result<(), DbError> {
+        self.db_main
+            .remap_types::<Str, ZeroCopyCodec<Vec<[u32; 2]>>>()
+            .put(rwtxn, db_constants::KEY_CHUNKS, chunks.ids())?;
+        Ok(())
+    }
+
+    /// Reads the phrases indexed as a single token, indexes
+    /// that don't have them return no phrases.
+    fn read_phrases(
+        rotxn: &RoTxn,
+        db_main: Database<Unspecified, Unspecified>,
+    ) -> Result<Phrases, DbError> {
+        let phrases = db_main
+            .remap_types::<Str, ZeroCopyCodec<HashSet<Box<str>>>>()
+            .get(rotxn, db_constants::KEY_PHRASES)?;
+
+        match phrases {
+            Some(phrases) => Ok(Phrases::from(deserialize::<
+                HashSet<Box<str>>,
+                rkyv::rancor::Error,
+            >(phrases)?)),
+            None => Ok(Phrases::default()),
+        }
+    }
+
+    pub fn write_phrases(&self, rwtxn: &mut RwTxn, phrases: &Phrases) -> Result<(), DbError> {
+        self.db_main
+            .remap_types::<Str, ZeroCopyCodec<HashSet<Box<str>>>>()
+            .put(rwtxn, db_constants::KEY_PHRASES, phrases.phrases())?;
+        Ok(())
+    }
+
+    /// Reads the maximum number of merged tokens, indexes
+    /// that don't have it use [DEFAULT_WINDOW_LEN].
+    fn read_window_len(
+        rotxn: &RoTxn,
+        db_main: Database<Unspecified, Unspecified>,
+    ) -> Result<NonZero<usize>, DbError> {
+        let window_len = db_main
+            .remap_types::<Str, ZeroCopyCodec<u64>>()
+            .get(rotxn, db_constants::KEY_WINDOW_LEN)?
+            .and_then(|window_len| NonZero::new(window_len.to_native() as usize))
+            .unwrap_or(DEFAULT_WINDOW_LEN);
+        Ok(window_len)
+    }
+
+    pub fn write_window_len(
+        &self,
+        rwtxn: &mut RwTxn,
+        window_len: NonZero<usize>,
+    ) -> Result<(), DbError> {
+        self.db_main.remap_types::<Str, ZeroCopyCodec<u64>>().put(
+            rwtxn,
+            db_constants::KEY_WINDOW_LEN,
+            &(window_len.get() as u64),
+        )?;
+        Ok(())
+    }
+
+    /// Reads the id of the index, if it has one.
+    fn read_index_id(
+        rotxn: &RoTxn,
+        db_main: Database<Unspecified, Unspecified>,
+    ) -> Result<Option<u64>, DbError> {
+        let index_id = db_main
+            .remap_types::<Str, ZeroCopyCodec<u64>>()
+            .get(rotxn, db_constants::KEY_INDEX_ID)?
+            .map(|index_id| index_id.to_native());
+        Ok(index_id)
+    }
+
+    /// Writes the id of the index, this should be unique every
+    /// time the index is (re)generated.
+    pub fn write_index_id(&self, rwtxn: &mut RwTxn, index_id: u64) -> Result<(), DbError> {
+        self.db_main.remap_types::<Str, ZeroCopyCodec<u64>>().put(
+            rwtxn,
+            db_constants::KEY_INDEX_ID,
+            &index_id,
+        )?;
+        Ok(())
+    }
+
     pub fn write_common_tokens(
         &self,
         rwtxn: &mut RwTxn,
@@ -532,7 +1073,7 @@ impl<D: Document> DB<D> {
         let path = path.as_ref();
         let env = unsafe {
             EnvOpenOptions::new()
-                .max_dbs(2)
+                .max_dbs(3)
                 .flags(EnvFlags::READ_ONLY)
                 .open(path)?
         };
@@ -557,7 +1098,16 @@ impl<D: Document> DB<D> {
             .open_database(&rotxn, Some(db_constants::DB_TOKEN_TO_OFFSETS))?
             .ok_or_else(|| DbError::DatabaseError(db_constants::DB_TOKEN_TO_OFFSETS.to_string()))?;
 
+        let db_long_token_to_offsets =
+            env.open_database(&rotxn, Some(db_constants::DB_LONG_TOKEN_TO_OFFSETS))?;
+
         let common_tokens = Self::read_common_tokens(&rotxn, db_main)?;
+        let analyzer = Self::read_analyzer(&rotxn, db_main)?;
+        analyzer.check()?;
+        let chunks = Self::read_chunks(&rotxn, db_main)?;
+        let window_len = Self::read_window_len(&rotxn, db_main)?;
+        let phrases = Self::read_phrases(&rotxn, db_main)?;
+        let index_id = Self::read_index_id(&rotxn, db_main)?;
 
         rotxn.commit()?;
 
@@ -570,6 +1120,12 @@ impl<D: Document> DB<D> {
                 db_main,
                 db_doc_id_to_document,
                 db_token_to_offsets,
+                db_long_token_to_offsets,
+                analyzer,
+                chunks,
+                window_len,
+                phrases,
+                index_id,
             },
             common_tokens,
             mmap,
@@ -578,22 +1134,29 @@ impl<D: Document> DB<D> {
 
     // This function neeeds to be inline never, for some reason inlining this
     // function makes some queries performance unpredictable
+    //
+    // The Roaringish Packed of the tokens are stored in `token_to_packed`, this
+    // map can be shared between multiple queries to avoid fetching the same token
+    // multiple times.
+    //
+    // Merged tokens that are missing from the index are skipped, so the query
+    // falls back to the individual tokens, that are always indexed.
+    //
+    // The positions expanded by the synonyms need to already be in
+    // `token_to_packed`, see [Self::union_alternatives].
     #[inline(never)]
+    #[allow(clippy::too_many_arguments)]
     fn merge_and_minimize_tokens<'a, 'b, 'alloc>(
         &self,
         rotxn: &RoTxn,
         tokens: RefTokens<'a>,
         common_tokens: &HashSet<Box<str>>,
+        phrases: &Phrases,
+        token_to_packed: &mut GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'b, Aligned>>,
         mmap: &'b Mmap,
 
         bump: &'alloc Bump,
-    ) -> Result<
-        (
-            Vec<RefTokens<'a>>,
-            GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'b, Aligned>>,
-        ),
-        SearchError,
-    > {
+    ) -> Result<Vec<RefTokens<'a>>, SearchError> {
         #[inline(always)]
         fn check_before_recursion<'a, 'b, 'alloc, D: Document>(
             me: &DB<D>,
@@ -617,12 +1180,15 @@ impl<D: Document> DB<D> {
                     let packed = me.get_roaringish_packed(rotxn, &tokens[0], mmap)?;
                     let score = packed.len();
                     e.insert(packed);
-
-                    let linked_list = bump.alloc(RefTokenLinkedList { tokens, next: None });
-                    memo_token_to_score_choices.insert(tokens, (score, linked_list));
                     score
                 }
             };
+
+            // the token might already be in `token_to_packed`, because it was
+            // fetched by a previous query or as the prefix of a sub problem,
+            // so always add it to the memo
+            let linked_list = bump.alloc(RefTokenLinkedList { tokens, next: None });
+            memo_token_to_score_choices.insert(tokens, (score, linked_list));
             Ok(Some(score))
         }
 
@@ -632,6 +1198,7 @@ impl<D: Document> DB<D> {
             rotxn: &RoTxn,
             tokens: RefTokens<'a>,
             common_tokens: &HashSet<Box<str>>,
+            phrases: &Phrases,
             token_to_packed: &mut GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'b, Aligned>>,
             mmap: &'b Mmap,
             memo_token_to_score_choices: &mut GxHashMap<
@@ -641,7 +1208,7 @@ impl<D: Document> DB<D> {
 
             bump: &'alloc Bump,
         ) -> Result<usize, SearchError> {
-            const { assert!(MAX_WINDOW_LEN.get() == 3) };
+            let window_len = me.window_len.get();
             let mut final_score = usize::MAX;
             let mut best_token_choice = None;
             let mut best_rem_choice = None;
@@ -650,25 +1217,39 @@ impl<D: Document> DB<D> {
             let mut end = tokens
                 .iter()
                 .skip(1)
-                .take(MAX_WINDOW_LEN.get() - 1)
+                .take(window_len - 1)
                 .take_while(|t| common_tokens.contains(*t))
                 .count()
                 + 2;
             if common_tokens.contains(&tokens[0]) {
                 end += 1;
             }
-            end = end.min(MAX_WINDOW_LEN.get() + 1).min(tokens.len() + 1);
+            end = end.min(window_len + 1).min(tokens.len() + 1);
+
+            // the phrases can be longer than the window
+            let phrases_end = phrases.max_len().min(tokens.len()) + 1;
 
-            for i in (1..end).rev() {
+            for i in (1..end.max(phrases_end)).rev() {
                 let (tokens, rem) = tokens.split_at(i);
+                if i >= end && !phrases.contains(tokens.tokens()) {
+                    continue;
+                }
 
                 let score = match token_to_packed.entry(tokens) {
                     Entry::Occupied(e) => e.get().len(),
                     Entry::Vacant(e) => {
-                        let packed = me.get_roaringish_packed(rotxn, tokens.tokens(), mmap)?;
-                        let score = packed.len();
-                        e.insert(packed);
-                        score
+                        match me.get_roaringish_packed(rotxn, tokens.tokens(), mmap) {
+                            Ok(packed) => {
+                                let score = packed.len();
+                                e.insert(packed);
+                                score
+                            }
+                            // the merged token might be missing, e.g. the index was built
+                            // with other common tokens, so fall back to shorter tokens,
+                            // only a missing single token means that nothing matches
+                            Err(SearchError::TokenNotFound(_)) if i > 1 => continue,
+                            Err(e) => return Err(e),
+                        }
                     }
                 };
 
@@ -692,6 +1273,7 @@ impl<D: Document> DB<D> {
                                     rotxn,
                                     rem,
                                     common_tokens,
+                                    phrases,
                                     token_to_packed,
                                     mmap,
                                     memo_token_to_score_choices,
@@ -737,67 +1319,75 @@ impl<D: Document> DB<D> {
             me: &DB<D>,
             rotxn: &RoTxn,
             tokens: RefTokens<'a>,
+            token_to_packed: &mut GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'b, Aligned>>,
             mmap: &'b Mmap,
-        ) -> Result<
-            (
-                Vec<RefTokens<'a>>,
-                GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'b, Aligned>>,
-            ),
-            SearchError,
-        > {
+        ) -> Result<Vec<RefTokens<'a>>, SearchError> {
             let l = tokens.len();
-            let mut token_to_packed = GxHashMap::with_capacity(l);
             let mut v = Vec::with_capacity(l);
 
             for token in tokens.ref_token_iter() {
-                let packed = me.get_roaringish_packed(rotxn, token.tokens(), mmap)?;
-                token_to_packed.insert(token, packed);
+                if let Entry::Vacant(e) = token_to_packed.entry(token) {
+                    let packed = me.get_roaringish_packed(rotxn, token.tokens(), mmap)?;
+                    e.insert(packed);
+                }
                 v.push(token);
             }
 
-            return Ok((v, token_to_packed));
+            return Ok(v);
         }
 
-        if common_tokens.is_empty() {
-            return no_common_tokens(self, rotxn, tokens, mmap);
+        if common_tokens.is_empty() && phrases.is_empty() {
+            return no_common_tokens(self, rotxn, tokens, token_to_packed, mmap);
         }
 
-        let len = tokens.reserve_len();
+        let len = tokens.reserve_len(self.window_len);
         let mut memo_token_to_score_choices = GxHashMap::with_capacity(len);
-        let mut token_to_packed = GxHashMap::with_capacity(len);
 
-        let score = match check_before_recursion(
-            self,
-            rotxn,
-            tokens,
-            &mut token_to_packed,
-            mmap,
-            &mut memo_token_to_score_choices,
-            bump,
-        )? {
-            Some(score) => score,
-            None => inner_merge_and_minimize_tokens(
+        // the merged tokens can't contain the tokens that can't
+        // be merged, so each run is minimized on its own
+        let mut final_tokens = Vec::with_capacity(tokens.len());
+        for tokens in tokens.segments() {
+            let score = match check_before_recursion(
                 self,
                 rotxn,
                 tokens,
-                common_tokens,
-                &mut token_to_packed,
+                token_to_packed,
                 mmap,
                 &mut memo_token_to_score_choices,
                 bump,
-            )?,
-        };
+            )? {
+                Some(score) => score,
+                None => inner_merge_and_minimize_tokens(
+                    self,
+                    rotxn,
+                    tokens,
+                    common_tokens,
+                    phrases,
+                    token_to_packed,
+                    mmap,
+                    &mut memo_token_to_score_choices,
+                    bump,
+                )?,
+            };
 
-        if score == 0 {
-            return Err(SearchError::MergeAndMinimizeNotPossible);
-        }
-        match memo_token_to_score_choices.remove(&tokens) {
-            Some((_, choices)) => {
-                let v = choices.iter().copied().collect();
-                Ok((v, token_to_packed))
+            if score == 0 {
+                return Err(SearchError::MergeAndMinimizeNotPossible);
+            }
+            let Some((_, choices)) = memo_token_to_score_choices.remove(&tokens) else {
+                return Err(SearchError::MergeAndMinimizeNotPossible);
+            };
+
+            // the memoized choices can come from the same tokens somewhere
+            // else in the query, so take them from this segment to keep
+            // their positions
+            let mut rem = tokens;
+            for choice in choices.iter() {
+                let (choice, r) = rem.split_at(choice.len());
+                final_tokens.push(choice);
+                rem = r;
             }
-            None => Err(SearchError::MergeAndMinimizeNotPossible),
         }
+        Ok(final_tokens)
     }
 
     fn get_roaringish_packed_from_offset<'a>(
@@ -821,6 +1411,11 @@ impl<D: Document> DB<D> {
         Ok(BorrowRoaringishPacked::new_raw(packed))
     }
 
+    /// Analyzer of the documents and queries of the index.
+    pub(crate) fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
     #[inline(always)]
     pub fn get_roaringish_packed<'a>(
         &self,
@@ -828,6 +1423,10 @@ impl<D: Document> DB<D> {
         token: &str,
         mmap: &'a Mmap,
     ) -> Result<BorrowRoaringishPacked<'a, Aligned>, SearchError> {
+        if token.len() > MAX_KEY_LEN {
+            return self.get_long_roaringish_packed(rotxn, token, mmap);
+        }
+
         let offset = self
             .db_token_to_offsets
             .get(rotxn, token)
@@ -838,52 +1437,645 @@ impl<D: Document> DB<D> {
         }
     }
 
+    /// Same as [Self::get_roaringish_packed], but for tokens longer than [MAX_KEY_LEN].
+    #[inline(never)]
+    fn get_long_roaringish_packed<'a>(
+        &self,
+        rotxn: &RoTxn,
+        token: &str,
+        mmap: &'a Mmap,
+    ) -> Result<BorrowRoaringishPacked<'a, Aligned>, SearchError> {
+        let not_found = || SearchError::TokenNotFound(token.to_string());
+        let db_long_token_to_offsets = self.db_long_token_to_offsets.ok_or_else(not_found)?;
+        let long_tokens = db_long_token_to_offsets
+            .get(rotxn, &long_token_hash(token))
+            .map_err(|e| DbError::from(e))?
+            .ok_or_else(not_found)?;
+
+        let long_token = long_tokens
+            .iter()
+            .find(|long_token| *long_token.token == *token)
+            .ok_or_else(not_found)?;
+        Self::get_roaringish_packed_from_offset(&long_token.offset, mmap)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn search<I: Intersection>(
         &self,
         q: &str,
         stats: &Stats,
         common_tokens: &HashSet<Box<str>>,
+        synonyms: Option<&SynonymMap>,
         mmap: &Mmap,
+        cache: Option<&SearchCache>,
+        threads: NonZero<usize>,
+        options: &SearchOptions,
+        within: Option<&DocSet>,
     ) -> Result<Vec<u32>, SearchError> {
         stats.iters.fetch_add(1, Relaxed);
 
         let b = std::time::Instant::now();
-        let tokens = Tokens::new(q);
-        let tokens = tokens.as_ref();
+        let expansions = Tokens::expand(q, &self.analyzer, options.exact(), synonyms)?;
         stats
             .normalize_tokenize
             .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
 
+        Self::union_expansions(expansions.iter().map(|tokens| {
+            self.search_expansion::<I>(
+                tokens,
+                stats,
+                common_tokens,
+                mmap,
+                cache,
+                threads,
+                options,
+                within,
+            )
+        }))
+    }
+
+    /// Combines the results of the expansions of a query by the synonyms.
+    ///
+    /// Expansions that don't match anything are ignored, if none of them
+    /// match the error of the first one (the query itself) is returned.
+    fn union_expansions(
+        results: impl IntoIterator<Item = Result<Vec<u32>, SearchError>>,
+    ) -> Result<Vec<u32>, SearchError> {
+        let ignore = |e: &SearchError| {
+            matches!(
+                e,
+                SearchError::TokenNotFound(_)
+                    | SearchError::EmptyIntersection
+                    | SearchError::MergeAndMinimizeNotPossible
+            )
+        };
+
+        let mut results = results.into_iter().peekable();
+        // this can't fail, there is always at least one expansion
+        let first = results.next().unwrap();
+        if results.peek().is_none() {
+            return first;
+        }
+
+        let mut result = None;
+        let mut first_err = None;
+        for r in std::iter::once(first).chain(results) {
+            match r {
+                Ok(doc_ids) => {
+                    let doc_ids = DocSet::from_sorted(doc_ids);
+                    result = Some(match result {
+                        Some(result) => doc_ids.union(&result),
+                        None => doc_ids,
+                    });
+                }
+                Err(e) if ignore(&e) => {
+                    first_err.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        match (result, first_err) {
+            (Some(result), _) => Ok(result.into()),
+            (None, Some(e)) => Err(e),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    /// Key of the cached result of `tokens`, if the results
+    /// of this index can be cached.
+    fn cache_key<I: Intersection>(
+        &self,
+        tokens: &Tokens,
+        options: &SearchOptions,
+    ) -> Option<CacheKey> {
+        self.index_id.map(|index_id| {
+            CacheKey::new(
+                index_id,
+                tokens.as_ref().tokens(),
+                options.exact(),
+                tokens.merge,
+                I::ALGORITHM,
+            )
+        })
+    }
+
+    /// Searches by one of the expansions of a query.
+    #[allow(clippy::too_many_arguments)]
+    fn search_expansion<I: Intersection>(
+        &self,
+        tokens: &Tokens,
+        stats: &Stats,
+        common_tokens: &HashSet<Box<str>>,
+        mmap: &Mmap,
+        cache: Option<&SearchCache>,
+        threads: NonZero<usize>,
+        options: &SearchOptions,
+        within: Option<&DocSet>,
+    ) -> Result<Vec<u32>, SearchError> {
+        let merge = tokens.merge;
+        let cache = cache.zip(self.cache_key::<I>(tokens, options));
+        let tokens = tokens.as_ref();
+
+        let no_common_tokens = HashSet::new();
+        let no_phrases = Phrases::default();
+        let (common_tokens, phrases) = match merge {
+            true => (common_tokens, &self.phrases),
+            false => (&no_common_tokens, &no_phrases),
+        };
+
         if tokens.is_empty() {
             return Err(SearchError::EmptyQuery);
         }
+        options.check_num_tokens(tokens.len())?;
+
+        if let Some(doc_ids) = cache.as_ref().and_then(|(c, key)| c.get(key)) {
+            return match within {
+                Some(within) => Ok(DocSet::from_sorted(doc_ids).intersection(within).into()),
+                None => Ok(doc_ids),
+            };
+        }
+
+        // the cache only has complete results
+        let (Some((cache, key)), None) = (cache, within) else {
+            // the chunks of the documents in `within` can also match
+            let expanded;
+            let within = match within {
+                Some(within) if !self.chunks.is_empty() => {
+                    expanded = self.chunks.expand(within);
+                    Some(&expanded)
+                }
+                within => within,
+            };
+            let doc_ids = self.search_tokens::<I>(
+                tokens,
+                stats,
+                common_tokens,
+                phrases,
+                mmap,
+                threads,
+                options,
+                within,
+            );
+            return self.resolve_chunks(doc_ids);
+        };
+
+        let doc_ids = self.search_tokens::<I>(
+            tokens,
+            stats,
+            common_tokens,
+            phrases,
+            mmap,
+            threads,
+            options,
+            None,
+        );
+        let doc_ids = self.resolve_chunks(doc_ids)?;
+        cache.insert(key, &doc_ids);
+        Ok(doc_ids)
+    }
+
+    /// Replaces the IDs of the chunks in the result of a search, including
+    /// the partial result of [SearchError::DeadlineExceeded], by the IDs of
+    /// their documents.
+    fn resolve_chunks(&self, r: Result<Vec<u32>, SearchError>) -> Result<Vec<u32>, SearchError> {
+        match r {
+            Ok(doc_ids) => Ok(self.chunks.resolve(doc_ids)),
+            Err(SearchError::DeadlineExceeded(partial)) => Err(SearchError::DeadlineExceeded(
+                partial.map(|doc_ids| self.chunks.resolve(doc_ids)),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Searches by the already normalized and tokenized `tokens`.
+    ///
+    /// If `within` is [Some] only the documents in it can match.
+    #[allow(clippy::too_many_arguments)]
+    fn search_tokens<I: Intersection>(
+        &self,
+        tokens: RefTokens,
+        stats: &Stats,
+        common_tokens: &HashSet<Box<str>>,
+        phrases: &Phrases,
+        mmap: &Mmap,
+        threads: NonZero<usize>,
+        options: &SearchOptions,
+        within: Option<&DocSet>,
+    ) -> Result<Vec<u32>, SearchError> {
+        options.check_interrupted(|| None)?;
 
         let rotxn = self.env.read_txn().map_err(|e| DbError::from(e))?;
+        let mut unions = Vec::new();
+        self.union_alternatives(&rotxn, tokens, mmap, &mut unions)?;
+
+        let filtered;
+        let mut token_to_packed = GxHashMap::with_capacity(tokens.reserve_len(self.window_len));
+        for (t, packed) in unions.iter() {
+            token_to_packed.insert(*t, BorrowRoaringishPacked::new(packed));
+        }
+
         if tokens.len() == 1 {
-            // this can't failt, we just checked
-            self.get_roaringish_packed(&rotxn, tokens.first().unwrap(), mmap)?
-                .get_doc_ids(stats);
+            let packed = match token_to_packed.get(&tokens) {
+                Some(packed) => *packed,
+                // this can't failt, we just checked
+                None => self.get_roaringish_packed(&rotxn, tokens.first().unwrap(), mmap)?,
+            };
+            if let Some(within) = within {
+                let packed = packed.filter_doc_ids(within);
+                options.check_intermediate_len(packed.len())?;
+                return Ok(BorrowRoaringishPacked::new(&packed).get_doc_ids(stats));
+            }
+            options.check_intermediate_len(packed.len())?;
+            return Ok(packed.get_doc_ids(stats));
         }
 
         let b = std::time::Instant::now();
-        let bump = Bump::with_capacity(tokens.reserve_len() * 5);
-        let (final_tokens, token_to_packed) =
-            self.merge_and_minimize_tokens(&rotxn, tokens, common_tokens, mmap, &bump)?;
+        let bump = Bump::with_capacity(tokens.reserve_len(self.window_len) * 5);
+        let final_tokens = self.merge_and_minimize_tokens(
+            &rotxn,
+            tokens,
+            common_tokens,
+            phrases,
+            &mut token_to_packed,
+            mmap,
+            &bump,
+        )?;
         stats
             .merge_minimize
             .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
 
-        if final_tokens.is_empty() {
+        if let Some(within) = within {
+            // all of the intersections depend on each other, so
+            // it's enough to filter the smallest Roaringish Packed
+            let smallest = final_tokens
+                .iter()
+                .filter_map(|t| token_to_packed.get(t).map(|p| (*t, *p)))
+                .min_by_key(|(_, p)| p.len());
+            if let Some((t, packed)) = smallest {
+                filtered = packed.filter_doc_ids(within);
+                token_to_packed.insert(t, BorrowRoaringishPacked::new(&filtered));
+            }
+        }
+
+        if threads.get() > 1 {
+            return Self::intersect_final_tokens_parallel::<I>(
+                &final_tokens,
+                &token_to_packed,
+                stats,
+                threads,
+                options,
+            );
+        }
+
+        Self::intersect_final_tokens::<I>(
+            &final_tokens,
+            &token_to_packed,
+            stats,
+            options,
+            None,
+            |_| {},
+        )
+    }
+
+    /// Unions the Roaringish Packed of the alternatives of each position
+    /// of `tokens` that was expanded by the synonyms into `unions`.
+    ///
+    /// Alternatives that aren't in the index are ignored, if none of them
+    /// are the position is left out, so searching it fails with
+    /// [SearchError::TokenNotFound].
+    fn union_alternatives<'a>(
+        &self,
+        rotxn: &RoTxn,
+        tokens: RefTokens<'a>,
+        mmap: &Mmap,
+        unions: &mut Vec<(RefTokens<'a>, RoaringishPacked)>,
+    ) -> Result<(), SearchError> {
+        for token in tokens.ref_token_iter() {
+            let alternatives = token.tokens();
+            if !alternatives.contains(ALTERNATIVES_SEPARATOR)
+                || unions.iter().any(|(t, _)| *t == token)
+            {
+                continue;
+            }
+
+            let mut packed = Vec::new();
+            for alternative in alternatives.split(ALTERNATIVES_SEPARATOR) {
+                match self.get_roaringish_packed(rotxn, alternative, mmap) {
+                    Ok(p) => packed.push(p),
+                    Err(SearchError::TokenNotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if !packed.is_empty() {
+                unions.push((token, BorrowRoaringishPacked::union(&packed)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Searches by multiple queries at once.
+    ///
+    /// All of the queries share the same read transaction and the
+    /// Roaringish Packed of each token is only fetched once. Queries that
+    /// start the intersection with the same pair of tokens reuse the result.
+    ///
+    /// The limits in `options` are applied to each query, but the
+    /// deadline and cancellation are shared by all of them.
+    ///
+    /// The outer [Result] is only an error if the transaction
+    /// can't be created or the index can't be read.
+    #[allow(clippy::too_many_arguments)]
+    pub fn search_many<I: Intersection>(
+        &self,
+        queries: &[&str],
+        stats: &Stats,
+        common_tokens: &HashSet<Box<str>>,
+        synonyms: Option<&SynonymMap>,
+        mmap: &Mmap,
+        cache: Option<&SearchCache>,
+        options: &SearchOptions,
+    ) -> Result<Vec<Result<Vec<u32>, SearchError>>, SearchError> {
+        stats.iters.fetch_add(queries.len() as u64, Relaxed);
+
+        let b = std::time::Instant::now();
+        // queries that can't be expanded have no expansions, only the error
+        let mut errors = Vec::with_capacity(queries.len());
+        let expansions: Vec<_> = queries
+            .iter()
+            .map(
+                |q| match Tokens::expand(q, &self.analyzer, options.exact(), synonyms) {
+                    Ok(expansions) => {
+                        errors.push(None);
+                        expansions
+                    }
+                    Err(e) => {
+                        errors.push(Some(e));
+                        Vec::new()
+                    }
+                },
+            )
+            .collect();
+        stats
+            .normalize_tokenize
+            .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
+
+        let no_common_tokens = HashSet::new();
+        let no_phrases = Phrases::default();
+        let rotxn = self.env.read_txn().map_err(|e| DbError::from(e))?;
+
+        let mut unions = Vec::new();
+        for tokens in expansions.iter().flatten() {
+            self.union_alternatives(&rotxn, tokens.as_ref(), mmap, &mut unions)?;
+        }
+
+        enum Plan<'a> {
+            Cached(Vec<u32>),
+            Intersect(Vec<RefTokens<'a>>),
+        }
+
+        let b = std::time::Instant::now();
+        let mut bump = Bump::new();
+        let mut token_to_packed = GxHashMap::new();
+        for (t, packed) in unions.iter() {
+            token_to_packed.insert(*t, BorrowRoaringishPacked::new(packed));
+        }
+        let mut shared = SharedIntersections::default();
+        let plans: Vec<Vec<_>> = expansions
+            .iter()
+            .map(|expansions| {
+                expansions
+                    .iter()
+                    .map(|tokens| -> Result<Plan, SearchError> {
+                        let (common_tokens, phrases) = match tokens.merge {
+                            true => (common_tokens, &self.phrases),
+                            false => (&no_common_tokens, &no_phrases),
+                        };
+                        let key = cache.and(self.cache_key::<I>(tokens, options));
+                        let tokens = tokens.as_ref();
+                        if tokens.is_empty() {
+                            return Err(SearchError::EmptyQuery);
+                        }
+                        options.check_num_tokens(tokens.len())?;
+
+                        if let Some(doc_ids) =
+                            cache.zip(key.as_ref()).and_then(|(c, key)| c.get(key))
+                        {
+                            return Ok(Plan::Cached(doc_ids));
+                        }
+
+                        options.check_interrupted(|| None)?;
+                        bump.reset();
+                        let final_tokens = self.merge_and_minimize_tokens(
+                            &rotxn,
+                            tokens,
+                            common_tokens,
+                            phrases,
+                            &mut token_to_packed,
+                            mmap,
+                            &bump,
+                        )?;
+
+                        if final_tokens.len() > 1 {
+                            let i = Self::first_pair(&final_tokens, &token_to_packed)?;
+                            shared.add(&final_tokens[i], &final_tokens[i + 1]);
+                        }
+
+                        Ok(Plan::Intersect(final_tokens))
+                    })
+                    .collect()
+            })
+            .collect();
+        stats
+            .merge_minimize
+            .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
+
+        let results = plans
+            .into_iter()
+            .zip(expansions.iter())
+            .zip(errors)
+            .map(|((plans, expansions), error)| {
+                if let Some(e) = error {
+                    return Err(e);
+                }
+
+                Self::union_expansions(plans.into_iter().zip(expansions.iter()).map(
+                    |(plan, tokens)| {
+                        let final_tokens = match plan? {
+                            Plan::Cached(doc_ids) => return Ok(doc_ids),
+                            Plan::Intersect(final_tokens) => final_tokens,
+                        };
+
+                        let doc_ids = Self::intersect_final_tokens::<I>(
+                            &final_tokens,
+                            &token_to_packed,
+                            stats,
+                            options,
+                            Some(&mut shared),
+                            |_| {},
+                        );
+                        let doc_ids = self.resolve_chunks(doc_ids)?;
+
+                        if let Some((cache, key)) = cache.zip(self.cache_key::<I>(tokens, options))
+                        {
+                            cache.insert(key, &doc_ids);
+                        }
+                        Ok(doc_ids)
+                    },
+                ))
+            })
+            .collect();
+
+        Ok(results)
+    }
+
+    /// Same as [Self::search], but instead of the document IDs returns
+    /// an explanation of how the query was planned and executed.
+    ///
+    /// The query is analyzed and limited by `options` the same way as
+    /// in [Self::search], so the plan is the one that the search runs.
+    ///
+    /// Each expansion of the query by the `synonyms` has its own plan, the
+    /// ones that don't match anything are left out. If none of them match
+    /// the error of the first one (the query itself) is returned.
+    pub fn explain<I: Intersection>(
+        &self,
+        q: &str,
+        stats: &Stats,
+        common_tokens: &HashSet<Box<str>>,
+        synonyms: Option<&SynonymMap>,
+        mmap: &Mmap,
+        options: &SearchOptions,
+    ) -> Result<Explain, SearchError> {
+        let expansions = Tokens::expand(q, &self.analyzer, options.exact(), synonyms)?;
+        let mut explains = Vec::with_capacity(expansions.len());
+        let mut first_err = None;
+        for tokens in expansions.iter() {
+            match self.explain_expansion::<I>(tokens, stats, common_tokens, mmap, options) {
+                Ok(explain) => explains.push(explain),
+                Err(
+                    e @ (SearchError::TokenNotFound(_) | SearchError::MergeAndMinimizeNotPossible),
+                ) => {
+                    first_err.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut explains = explains.into_iter();
+        match (explains.next(), first_err) {
+            (Some(mut explain), _) => {
+                explain.alternatives = explains.collect();
+                Ok(explain)
+            }
+            (None, Some(e)) => Err(e),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    /// Explains one of the expansions of a query.
+    fn explain_expansion<I: Intersection>(
+        &self,
+        tokens: &Tokens,
+        stats: &Stats,
+        common_tokens: &HashSet<Box<str>>,
+        mmap: &Mmap,
+        options: &SearchOptions,
+    ) -> Result<Explain, SearchError> {
+        let merge = tokens.merge;
+        let tokens = tokens.as_ref();
+        if tokens.is_empty() {
             return Err(SearchError::EmptyQuery);
         }
+        options.check_num_tokens(tokens.len())?;
 
-        if final_tokens.len() == 1 {
-            return token_to_packed
-                .get(&final_tokens[0])
-                .ok_or_else(|| SearchError::TokenNotFound(final_tokens[0].tokens().to_string()))
-                .map(|p| p.get_doc_ids(stats));
+        let no_common_tokens = HashSet::new();
+        let no_phrases = Phrases::default();
+        let (common_tokens, phrases) = match merge {
+            true => (common_tokens, &self.phrases),
+            false => (&no_common_tokens, &no_phrases),
+        };
+
+        let rotxn = self.env.read_txn().map_err(|e| DbError::from(e))?;
+        let mut unions = Vec::new();
+        self.union_alternatives(&rotxn, tokens, mmap, &mut unions)?;
+
+        let bump = Bump::with_capacity(tokens.reserve_len(self.window_len) * 5);
+        let mut token_to_packed = GxHashMap::with_capacity(tokens.reserve_len(self.window_len));
+        for (t, packed) in unions.iter() {
+            token_to_packed.insert(*t, BorrowRoaringishPacked::new(packed));
         }
+        let final_tokens = self.merge_and_minimize_tokens(
+            &rotxn,
+            tokens,
+            common_tokens,
+            phrases,
+            &mut token_to_packed,
+            mmap,
+            &bump,
+        )?;
+
+        let span = |(b, e): (usize, usize)| -> String {
+            final_tokens[b..e]
+                .iter()
+                .map(|t| display_token(t.tokens()))
+                .intersperse(" ".to_string())
+                .collect()
+        };
+
+        let mut steps = Vec::new();
+        let r = Self::intersect_final_tokens::<I>(
+            &final_tokens,
+            &token_to_packed,
+            stats,
+            options,
+            None,
+            |step| {
+                steps.push(ExplainStep {
+                    lhs: span(step.lhs),
+                    rhs: span(step.rhs),
+                    lhs_len: step.lhs_len,
+                    lhs_packed_len: step.lhs_packed_len,
+                    rhs_packed_len: step.rhs_packed_len,
+                    first_phase: step.algorithms.map(|a| a[0]),
+                    second_phase: step.algorithms.map(|a| a[1]),
+                    result_len: step.result_len,
+                    elapsed: step.elapsed,
+                })
+            },
+        );
+        let num_documents = match self.resolve_chunks(r) {
+            Ok(doc_ids) => doc_ids.len(),
+            Err(SearchError::EmptyIntersection) => 0,
+            Err(e) => return Err(e),
+        };
+
+        let final_tokens = final_tokens
+            .iter()
+            .map(|t| ExplainToken {
+                token: display_token(t.tokens()),
+                num_merged: t.len(),
+                packed_len: token_to_packed.get(t).map(|p| p.len()).unwrap_or(0),
+            })
+            .collect();
+
+        Ok(Explain {
+            tokens: tokens.iter().map(display_token).collect(),
+            final_tokens,
+            steps,
+            num_documents,
+            alternatives: Vec::new(),
+        })
+    }
 
+    /// Finds the index of the pair of adjacent tokens with the smallest
+    /// Roaringish Packed, which is where the intersection starts.
+    ///
+    /// `final_tokens` needs to have at least 2 tokens.
+    fn first_pair<'a>(
+        final_tokens: &[RefTokens<'a>],
+        token_to_packed: &GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'_, Aligned>>,
+    ) -> Result<usize, SearchError> {
         // at this point we know that we have at least
         // 2 tokens, so the loop will run at least once
         // changing the value of `i` to be inbounds
@@ -906,21 +2098,123 @@ impl<D: Document> DB<D> {
                 min = l;
             }
         }
+        Ok(i)
+    }
+
+    /// Upper bound of the number of elements allocated by intersecting
+    /// `lhs` with `rhs`.
+    ///
+    /// The result is bounded by the smallest side, but the first pass
+    /// also allocates a buffer as big as `lhs` for the positions that
+    /// cross a group boundary.
+    fn intersection_alloc_len(
+        lhs: BorrowRoaringishPacked<'_, Aligned>,
+        rhs: BorrowRoaringishPacked<'_, Aligned>,
+    ) -> usize {
+        (lhs.len() + 1).max(lhs.len().min(rhs.len()))
+    }
+
+    /// Upper bound of the number of elements allocated by any of the steps of
+    /// [Self::intersect_final_tokens] starting at the pair `i`, following the
+    /// same order and bounding each intermediate result by its smallest side.
+    fn max_intersection_alloc_len(
+        packed: &[BorrowRoaringishPacked<'_, Aligned>],
+        i: usize,
+    ) -> usize {
+        let mut max = Self::intersection_alloc_len(packed[i], packed[i + 1]);
+        let mut result_len = packed[i].len().min(packed[i + 1].len());
+
+        let mut left_i = i.wrapping_sub(1);
+        let mut right_i = i + 2;
+        loop {
+            let go_left = match (packed.get(left_i), packed.get(right_i)) {
+                (Some(lhs), Some(rhs)) => lhs.len() <= rhs.len(),
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+
+            if go_left {
+                let lhs = packed[left_i].len();
+                max = max.max(lhs + 1);
+                result_len = result_len.min(lhs);
+                left_i = left_i.wrapping_sub(1);
+            } else {
+                max = max.max(result_len + 1);
+                result_len = result_len.min(packed[right_i].len());
+                right_i += 1;
+            }
+        }
+        max
+    }
+
+    /// Intersects the tokens chosen by the merge and minimize phase.
+    ///
+    /// Starts with the pair of adjacent tokens that has the smallest
+    /// Roaringish Packed and then grows the result to the left or right,
+    /// always picking the side with the smallest Roaringish Packed.
+    ///
+    /// If `shared` is [Some] the intersection of the first pair of tokens
+    /// can be reused between queries.
+    ///
+    /// The limits in `options` are checked before each intersection.
+    ///
+    /// `on_step` is called after each intersection.
+    fn intersect_final_tokens<'a, I: Intersection>(
+        final_tokens: &[RefTokens<'a>],
+        token_to_packed: &GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'_, Aligned>>,
+        stats: &Stats,
+        options: &SearchOptions,
+        shared: Option<&mut SharedIntersections>,
+        mut on_step: impl FnMut(IntersectStep),
+    ) -> Result<Vec<u32>, SearchError> {
+        if final_tokens.is_empty() {
+            return Err(SearchError::EmptyQuery);
+        }
+
+        if final_tokens.len() == 1 {
+            let packed = token_to_packed
+                .get(&final_tokens[0])
+                .ok_or_else(|| SearchError::TokenNotFound(final_tokens[0].tokens().to_string()))?;
+            options.check_intermediate_len(packed.len())?;
+            return Ok(packed.get_doc_ids(stats));
+        }
 
-        let lhs = &final_tokens[i];
-        let mut lhs_len = lhs.len() as u32;
+        let i = Self::first_pair(final_tokens, token_to_packed)?;
+
+        // the result is at the position of its rightmost token, so the
+        // distances are the differences of the positions in the query
+        let t_lhs = &final_tokens[i];
         let lhs = token_to_packed
-            .get(lhs)
-            .ok_or_else(|| SearchError::TokenNotFound(lhs.tokens().to_string()))?;
+            .get(t_lhs)
+            .ok_or_else(|| SearchError::TokenNotFound(t_lhs.tokens().to_string()))?;
 
-        let rhs = &final_tokens[i + 1];
-        let mut rhs_len = rhs.len() as u32;
+        let t_rhs = &final_tokens[i + 1];
+        let lhs_len = t_rhs.query_position() - t_lhs.query_position();
+        let mut anchor = t_rhs.query_position();
         let rhs = token_to_packed
-            .get(rhs)
-            .ok_or_else(|| SearchError::TokenNotFound(rhs.tokens().to_string()))?;
+            .get(t_rhs)
+            .ok_or_else(|| SearchError::TokenNotFound(t_rhs.tokens().to_string()))?;
+
+        options.check_interrupted(|| None)?;
+        options.check_intermediate_len(Self::intersection_alloc_len(*lhs, *rhs))?;
 
-        let mut result = lhs.intersect::<I>(*rhs, lhs_len, stats);
+        let b = std::time::Instant::now();
+        let (mut result, algorithms) = match shared {
+            Some(shared) => shared.intersect::<I>(t_lhs, t_rhs, *lhs, *rhs, stats),
+            None => lhs.intersect_with_algorithms::<I>(*rhs, lhs_len, stats),
+        };
         let mut result_borrow = BorrowRoaringishPacked::new(&result);
+        on_step(IntersectStep {
+            lhs: (i, i + 1),
+            rhs: (i + 1, i + 2),
+            lhs_len,
+            lhs_packed_len: lhs.len(),
+            rhs_packed_len: rhs.len(),
+            algorithms,
+            result_len: result.len(),
+            elapsed: b.elapsed(),
+        });
 
         let mut left_i = i.wrapping_sub(1);
         let mut right_i = i + 2;
@@ -928,7 +2222,7 @@ impl<D: Document> DB<D> {
         loop {
             let lhs = final_tokens.get(left_i);
             let rhs = final_tokens.get(right_i);
-            match (lhs, rhs) {
+            let go_left = match (lhs, rhs) {
                 (Some(t_lhs), Some(t_rhs)) => {
                     let lhs = token_to_packed
                         .get(t_lhs)
@@ -936,59 +2230,212 @@ impl<D: Document> DB<D> {
                     let rhs = token_to_packed
                         .get(t_rhs)
                         .ok_or_else(|| SearchError::TokenNotFound(t_rhs.tokens().to_string()))?;
-                    if lhs.len() <= rhs.len() {
-                        lhs_len += t_lhs.len() as u32;
-
-                        result = lhs.intersect::<I>(result_borrow, lhs_len, stats);
-                        result_borrow = BorrowRoaringishPacked::new(&result);
-
-                        left_i = left_i.wrapping_sub(1);
-                    } else {
-                        result = result_borrow.intersect::<I>(*rhs, rhs_len, stats);
-                        result_borrow = BorrowRoaringishPacked::new(&result);
+                    lhs.len() <= rhs.len()
+                }
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
 
-                        lhs_len += rhs_len;
-                        rhs_len = t_rhs.len() as u32;
+            options.check_interrupted(|| Some(result_borrow.get_doc_ids(stats)))?;
+
+            let b = std::time::Instant::now();
+            let result_range = (left_i.wrapping_add(1), right_i);
+            let step = if go_left {
+                // this can't fail, we just checked
+                let t_lhs = &final_tokens[left_i];
+                let lhs = token_to_packed
+                    .get(t_lhs)
+                    .ok_or_else(|| SearchError::TokenNotFound(t_lhs.tokens().to_string()))?;
+                let lhs_len = anchor - t_lhs.query_position();
+                options
+                    .check_intermediate_len(Self::intersection_alloc_len(*lhs, result_borrow))?;
+
+                let result_len = result_borrow.len();
+                let algorithms;
+                (result, algorithms) =
+                    lhs.intersect_with_algorithms::<I>(result_borrow, lhs_len, stats);
+                result_borrow = BorrowRoaringishPacked::new(&result);
+
+                let step = IntersectStep {
+                    lhs: (left_i, left_i + 1),
+                    rhs: result_range,
+                    lhs_len,
+                    lhs_packed_len: lhs.len(),
+                    rhs_packed_len: result_len,
+                    algorithms,
+                    result_len: result.len(),
+                    elapsed: b.elapsed(),
+                };
+                left_i = left_i.wrapping_sub(1);
+                step
+            } else {
+                // this can't fail, we just checked
+                let t_rhs = &final_tokens[right_i];
+                let rhs = token_to_packed
+                    .get(t_rhs)
+                    .ok_or_else(|| SearchError::TokenNotFound(t_rhs.tokens().to_string()))?;
+                options
+                    .check_intermediate_len(Self::intersection_alloc_len(result_borrow, *rhs))?;
+
+                let result_len = result_borrow.len();
+                let lhs_len = t_rhs.query_position() - anchor;
+                let algorithms;
+                (result, algorithms) =
+                    result_borrow.intersect_with_algorithms::<I>(*rhs, lhs_len, stats);
+                result_borrow = BorrowRoaringishPacked::new(&result);
+
+                let step = IntersectStep {
+                    lhs: result_range,
+                    rhs: (right_i, right_i + 1),
+                    lhs_len,
+                    lhs_packed_len: result_len,
+                    rhs_packed_len: rhs.len(),
+                    algorithms,
+                    result_len: result.len(),
+                    elapsed: b.elapsed(),
+                };
+                anchor = t_rhs.query_position();
+                right_i += 1;
+                step
+            };
+            on_step(step);
 
-                        right_i += 1;
-                    }
-                }
-                (Some(t_lhs), None) => {
-                    let lhs = token_to_packed
-                        .get(t_lhs)
-                        .ok_or_else(|| SearchError::TokenNotFound(t_lhs.tokens().to_string()))?;
-                    lhs_len += t_lhs.len() as u32;
+            if result.is_empty() {
+                return Err(SearchError::EmptyIntersection);
+            }
+        }
 
-                    result = lhs.intersect::<I>(result_borrow, lhs_len, stats);
-                    result_borrow = BorrowRoaringishPacked::new(&result);
+        Ok(result_borrow.get_doc_ids(stats))
+    }
 
-                    left_i = left_i.wrapping_sub(1);
-                }
-                (None, Some(t_rhs)) => {
-                    let rhs = token_to_packed
-                        .get(t_rhs)
-                        .ok_or_else(|| SearchError::TokenNotFound(t_rhs.tokens().to_string()))?;
+    /// Same as [Self::intersect_final_tokens], but the document IDs are
+    /// split in at most `threads` ranges that are intersected in parallel.
+    ///
+    /// Since the document IDs are ordered, the results of
+    /// each range can simply be concatenated.
+    fn intersect_final_tokens_parallel<'a, I: Intersection>(
+        final_tokens: &[RefTokens<'a>],
+        token_to_packed: &GxHashMap<RefTokens<'a>, BorrowRoaringishPacked<'_, Aligned>>,
+        stats: &Stats,
+        threads: NonZero<usize>,
+        options: &SearchOptions,
+    ) -> Result<Vec<u32>, SearchError> {
+        let packed = final_tokens
+            .iter()
+            .map(|t| {
+                token_to_packed
+                    .get(t)
+                    .copied()
+                    .ok_or_else(|| SearchError::TokenNotFound(t.tokens().to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-                    result = result_borrow.intersect::<I>(*rhs, rhs_len, stats);
-                    result_borrow = BorrowRoaringishPacked::new(&result);
+        // the biggest Roaringish Packed dominates the cost
+        // of the intersection, so use it to balance the ranges
+        let boundaries = match packed.iter().max_by_key(|p| p.len()) {
+            Some(biggest) if final_tokens.len() > 1 => biggest.partition_doc_ids(threads.get()),
+            _ => Vec::new(),
+        };
+        if boundaries.is_empty() {
+            return Self::intersect_final_tokens::<I>(
+                final_tokens,
+                token_to_packed,
+                stats,
+                options,
+                None,
+                |_| {},
+            );
+        }
 
-                    lhs_len += rhs_len;
-                    rhs_len = t_rhs.len() as u32;
+        // each range is checked individually, so check
+        // the size limit for all of the ranges together
+        let i = Self::first_pair(final_tokens, token_to_packed)?;
+        options.check_intermediate_len(Self::max_intersection_alloc_len(&packed, i))?;
 
-                    right_i += 1;
+        let begins = std::iter::once(0).chain(boundaries.iter().copied());
+        let ends = boundaries
+            .iter()
+            .copied()
+            .map(Some)
+            .chain(std::iter::once(None));
+        let results = std::thread::scope(|s| {
+            let handles: Vec<_> = begins
+                .zip(ends)
+                .map(|(begin, end)| {
+                    let packed = &packed;
+                    s.spawn(move || {
+                        let token_to_packed: GxHashMap<_, _> = final_tokens
+                            .iter()
+                            .zip(packed.iter())
+                            .map(|(t, p)| (*t, p.doc_id_range(begin, end)))
+                            .collect();
+
+                        // due to the alignment the range may also
+                        // contain documents from the previous range
+                        let skip_previous = |mut doc_ids: Vec<u32>| {
+                            let i = doc_ids.partition_point(|doc_id| *doc_id < begin);
+                            doc_ids.drain(..i);
+                            doc_ids
+                        };
+
+                        match Self::intersect_final_tokens::<I>(
+                            final_tokens,
+                            &token_to_packed,
+                            stats,
+                            options,
+                            None,
+                            |_| {},
+                        ) {
+                            Ok(doc_ids) => Ok(skip_previous(doc_ids)),
+                            Err(SearchError::EmptyIntersection) => Ok(Vec::new()),
+                            Err(SearchError::DeadlineExceeded(partial)) => {
+                                Err(SearchError::DeadlineExceeded(partial.map(skip_previous)))
+                            }
+                            Err(e) => Err(e),
+                        }
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or(Err(SearchError::InternalError)))
+                .collect::<Vec<_>>()
+        });
+
+        // if the deadline was exceeded only in some of the ranges,
+        // the finished ones are still part of the partial result
+        let mut exceeded = false;
+        let mut parts = Vec::with_capacity(results.len());
+        for r in results {
+            match r {
+                Ok(doc_ids) => parts.push(Some(doc_ids)),
+                Err(SearchError::DeadlineExceeded(partial)) => {
+                    exceeded = true;
+                    parts.push(partial);
                 }
-                (None, None) => break,
+                Err(e) => return Err(e),
             }
+        }
 
-            if result.is_empty() {
-                return Err(SearchError::EmptyIntersection);
-            }
+        if exceeded {
+            let partial = parts
+                .into_iter()
+                .collect::<Option<Vec<_>>>()
+                .map(|parts| parts.concat());
+            return Err(SearchError::DeadlineExceeded(partial));
         }
 
-        Ok(result_borrow.get_doc_ids(stats))
+        let doc_ids = parts.into_iter().flatten().collect::<Vec<_>>().concat();
+        // keep the same behavior as the serial version
+        if doc_ids.is_empty() && final_tokens.len() > 2 {
+            return Err(SearchError::EmptyIntersection);
+        }
+        Ok(doc_ids)
     }
 
-    fn inner_get_archived_document<'a>(
+    pub(crate) fn inner_get_archived_document<'a>(
         &self,
         rotxn: &'a RoTxn,
         doc_id: &u32,
@@ -1053,3 +2500,421 @@ impl<D: Document> DB<D> {
             .map_err(|e| GetDocumentError::DbError(DbError::from(e)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        num::NonZero,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use bumpalo::Bump;
+    use gxhash::{HashMap as GxHashMap, HashMapExt};
+    use heed::{
+        Database, EnvOpenOptions, RwTxn, Unspecified,
+        byteorder::NativeEndian,
+        types::{Bytes, Str, U64},
+    };
+
+    use super::{
+        ALTERNATIVES_SEPARATOR, DB, INITIAL_MAP_SIZE, LongToken, MAX_KEY_LEN, Offset, Tokens,
+        db_constants, long_token_hash,
+    };
+    use crate::{
+        Analyzer, CommonTokens, Indexer, NaiveIntersect, Punctuation, SearchCache, SearchError,
+        SearchOptions, Searcher, Stats, SynonymMap, codecs::ZeroCopyCodec, phrases::Phrases,
+        test_utils::TempDir,
+    };
+
+    fn docs() -> Vec<(&'static str, u32)> {
+        vec![
+            ("look at my beautiful cat", 0),
+            ("this is a document", 1),
+            ("look at my dog", 2),
+            ("look at my beautiful hamster", 3),
+            ("my beautiful cat looks at my dog", 4),
+        ]
+    }
+
+    #[test]
+    fn parallel_search_matches_single_threaded() {
+        const WORDS: [&str; 7] = ["a", "b", "c", "d", "e", "f", "g"];
+        // documents of different lengths, so the boundaries of the
+        // ranges fall in the middle of the groups of 8 elements
+        let docs: Vec<_> = (0..600u32)
+            .map(|i| {
+                let len = 3 + (i * 13 % 40) as usize;
+                let words: Vec<_> = (0..len)
+                    .map(|j| WORDS[(i as usize * 31 + j * 17 + j * j) % WORDS.len()])
+                    .collect();
+                (words.join(" "), i)
+            })
+            .collect();
+
+        let dir = TempDir::new("parallel_search");
+        let indexer = Indexer::new(Some(100), Some(CommonTokens::FixedNum(2)));
+        let (mut searcher, _) = indexer.index(docs, dir.path()).unwrap();
+
+        let mut queries = Vec::new();
+        for a in WORDS {
+            queries.push(a.to_string());
+            for b in WORDS {
+                queries.push(format!("{a} {b}"));
+                for c in WORDS {
+                    queries.push(format!("{a} {b} {c}"));
+                }
+            }
+        }
+        let expected: Vec<_> = queries
+            .iter()
+            .map(|q| format!("{:?}", searcher.search::<NaiveIntersect>(q).0))
+            .collect();
+        assert!(expected.iter().any(|r| r.starts_with("Ok")));
+
+        for threads in [1, 2, 3, 8, 64] {
+            searcher = searcher.with_threads(NonZero::new(threads).unwrap());
+            for (q, expected) in queries.iter().zip(expected.iter()) {
+                let r = searcher.search::<NaiveIntersect>(q);
+                assert_eq!(&format!("{:?}", r.0), expected, "{q} {threads}");
+            }
+        }
+    }
+
+    #[test]
+    fn deadline_returns_the_partial_result() {
+        let dir = TempDir::new("deadline_partial");
+        let (searcher, _) = Indexer::new(None, None).index(docs(), dir.path()).unwrap();
+        drop(searcher);
+
+        let (db, _, mmap) = DB::<u32>::open(dir.path()).unwrap();
+        let tokens = Tokens::new("look at my beautiful cat", &db.analyzer, false);
+        let rotxn = db.env.read_txn().unwrap();
+        let mut token_to_packed = GxHashMap::new();
+        let bump = Bump::new();
+        let final_tokens = db
+            .merge_and_minimize_tokens(
+                &rotxn,
+                tokens.as_ref(),
+                &HashSet::new(),
+                &Phrases::default(),
+                &mut token_to_packed,
+                &mmap,
+                &bump,
+            )
+            .unwrap();
+        assert!(final_tokens.len() > 2);
+
+        // the deadline passes after the first intersection
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let options = SearchOptions::new().with_deadline(deadline);
+        let r = DB::<u32>::intersect_final_tokens::<NaiveIntersect>(
+            &final_tokens,
+            &token_to_packed,
+            &Stats::default(),
+            &options,
+            None,
+            |_| std::thread::sleep(deadline.saturating_duration_since(Instant::now())),
+        );
+        let Err(SearchError::DeadlineExceeded(Some(partial))) = r else {
+            panic!("{r:?}");
+        };
+        // the partial result is a superset of the final one
+        assert!(partial.contains(&0));
+    }
+
+    #[test]
+    fn expand_joins_the_alternatives() {
+        let mut synonyms = SynonymMap::new();
+        synonyms.add("nyc", "new york city");
+        synonyms.add("nyc", "new jersey city");
+        let analyzer = Analyzer::default();
+        let synonyms = synonyms.normalized(&analyzer);
+
+        let expansions = Tokens::expand("NYC rocks", &analyzer, false, Some(&synonyms)).unwrap();
+        assert_eq!(expansions.len(), 2);
+        assert_eq!(expansions[0].tokens, "nyc rocks");
+        assert_eq!(expansions[0].mergeable, [true, true]);
+
+        let tokens = &expansions[1];
+        assert_eq!(
+            tokens.tokens,
+            format!("new jersey{ALTERNATIVES_SEPARATOR}york city rocks")
+        );
+        assert_eq!(tokens.query_positions, [0, 1, 2, 3]);
+        // positions with alternatives can't be merged
+        assert_eq!(tokens.mergeable, [true, false, true, true]);
+        let tokens: Vec<_> = tokens
+            .as_ref()
+            .ref_token_iter()
+            .map(|t| t.tokens().to_string())
+            .collect();
+        assert_eq!(tokens, ["new", "jersey\nyork", "city", "rocks"]);
+    }
+
+    #[test]
+    fn punctuation_in_phrases() {
+        let docs = vec![("a, b", 0), ("a b", 1), ("a x b", 2), ("b, a, b", 3)];
+        let cases = [
+            (Punctuation::Keep, [("a, b", vec![0, 3]), ("a b", vec![1])]),
+            (
+                Punctuation::Drop,
+                [("a, b", vec![0, 1, 3]), ("a b", vec![0, 1, 3])],
+            ),
+            // a gap only constrains the distance between the tokens
+            (
+                Punctuation::Gap,
+                [("a, b", vec![0, 2, 3]), ("a b", vec![1])],
+            ),
+        ];
+
+        for (punctuation, queries) in cases {
+            // with "a" as a common token the gaps can't be merged
+            for common in [None, Some("a")] {
+                let common_tokens = match common {
+                    Some(token) => CommonTokens::List([token.into()].into()),
+                    None => CommonTokens::FixedNum(0),
+                };
+                let dir = TempDir::new("punctuation");
+                let analyzer = Analyzer::new().with_punctuation(punctuation);
+                let indexer = Indexer::new(None, Some(common_tokens)).with_analyzer(analyzer);
+                let (searcher, _) = indexer.index(docs.clone(), dir.path()).unwrap();
+                for (q, expected) in queries.iter() {
+                    let r = searcher.search::<NaiveIntersect>(q).0.unwrap_or_default();
+                    assert_eq!(r, *expected, "{q} {punctuation:?} {common:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_tokens() {
+        let long_a = "a".repeat(MAX_KEY_LEN + 1);
+        let long_b = "b".repeat(2 * MAX_KEY_LEN);
+        let docs = vec![
+            (format!("x {long_a} y"), 0),
+            (format!("x {long_b} y"), 1),
+            (format!("{long_a} {long_b}"), 2),
+        ];
+        let dir = TempDir::new("long_tokens");
+        let (searcher, _) = Indexer::new(None, None).index(docs, dir.path()).unwrap();
+        let search = |searcher: &Searcher<u32>, q: &str| searcher.search::<NaiveIntersect>(q).0;
+        assert_eq!(search(&searcher, &long_a).unwrap(), [0, 2]);
+        assert_eq!(search(&searcher, &format!("x {long_b} y")).unwrap(), [1]);
+        assert_eq!(
+            search(&searcher, &format!("{long_a} {long_b}")).unwrap(),
+            [2]
+        );
+        drop(searcher);
+
+        // both tokens have the same hash
+        let env = unsafe { EnvOpenOptions::new().max_dbs(3).open(dir.path()).unwrap() };
+        let mut rwtxn = env.write_txn().unwrap();
+        let db: Database<U64<NativeEndian>, ZeroCopyCodec<Vec<LongToken>>> = env
+            .open_database(&rwtxn, Some(db_constants::DB_LONG_TOKEN_TO_OFFSETS))
+            .unwrap()
+            .unwrap();
+        let get = |rwtxn: &RwTxn, token: &str| -> Vec<LongToken> {
+            let long_tokens = db.get(rwtxn, &long_token_hash(token)).unwrap().unwrap();
+            long_tokens
+                .iter()
+                .map(|long_token| LongToken {
+                    token: long_token.token.as_ref().into(),
+                    offset: Offset {
+                        begin: long_token.offset.begin.to_native(),
+                        len: long_token.offset.len.to_native(),
+                    },
+                })
+                .collect()
+        };
+        let mut collision = get(&rwtxn, &long_b);
+        collision.extend(get(&rwtxn, &long_a));
+        db.put(&mut rwtxn, &long_token_hash(&long_a), &collision)
+            .unwrap();
+        rwtxn.commit().unwrap();
+        env.prepare_for_closing().wait();
+
+        let searcher = Searcher::<u32>::new(dir.path()).unwrap();
+        assert_eq!(search(&searcher, &long_a).unwrap(), [0, 2]);
+        assert_eq!(search(&searcher, &long_b).unwrap(), [1, 2]);
+        assert_eq!(search(&searcher, &format!("x {long_a}")).unwrap(), [0]);
+        let other = "a".repeat(MAX_KEY_LEN + 2);
+        assert!(matches!(
+            search(&searcher, &other),
+            Err(SearchError::TokenNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn grows_the_map() {
+        // the documents are big, but their content is small
+        let len = 1 << 16;
+        let num_docs = 2 * INITIAL_MAP_SIZE / len;
+        let docs = (0..num_docs).map(|i| {
+            let content = format!("doc {} of {num_docs}", i % 7);
+            let id = i.to_string();
+            (content, "0".repeat(len - id.len()) + &id)
+        });
+
+        let dir = TempDir::new("grows_the_map");
+        let (searcher, n) = Indexer::new(Some(300), None)
+            .index(docs, dir.path())
+            .unwrap();
+        assert_eq!(n as usize, num_docs);
+        let data = std::fs::metadata(dir.path().join("data.mdb")).unwrap();
+        assert!(data.len() > INITIAL_MAP_SIZE as u64);
+
+        let r = searcher.search::<NaiveIntersect>("doc 3 of");
+        let doc_ids = r.get_internal_document_ids().unwrap();
+        assert_eq!(doc_ids.len(), (0..num_docs).filter(|i| i % 7 == 3).count());
+        let doc = searcher.get_document(doc_ids[1]).unwrap();
+        assert_eq!(doc.len(), len);
+        assert_eq!(doc.trim_start_matches('0'), "10");
+    }
+
+    #[test]
+    fn search_many_matches_search() {
+        let dir = TempDir::new("search_many");
+        let indexer = Indexer::new(Some(2), Some(CommonTokens::FixedNum(3)));
+        let (searcher, _) = indexer.index(docs(), dir.path()).unwrap();
+
+        // most of the queries share the first pair of tokens
+        let queries = [
+            "look at my",
+            "look at my beautiful cat",
+            "look at my dog",
+            "look at my beautiful",
+            "at my beautiful",
+            "at my dog",
+            "my beautiful cat",
+            "look at my",
+            "beautiful hamster",
+            "a document",
+            "look at my bird",
+            "",
+        ];
+        let results = searcher.search_many::<NaiveIntersect>(&queries).unwrap();
+        assert_eq!(results.len(), queries.len());
+        assert_eq!(results[0].0.as_deref().unwrap(), [0, 2, 3]);
+        for (q, many) in queries.iter().zip(results) {
+            let one = searcher.search::<NaiveIntersect>(q);
+            assert_eq!(format!("{:?}", many.0), format!("{:?}", one.0), "{q}");
+        }
+    }
+
+    #[test]
+    fn indexes_without_id_are_not_cached() {
+        let dir = TempDir::new("without_index_id");
+        let docs = vec![("look at my cat", 0)];
+        let (searcher, _) = Indexer::new(None, None).index(docs, dir.path()).unwrap();
+        drop(searcher);
+
+        // indexes generated before the id existed don't have one
+        let env = unsafe { EnvOpenOptions::new().max_dbs(3).open(dir.path()).unwrap() };
+        let mut rwtxn = env.write_txn().unwrap();
+        let db_main: Database<Str, Unspecified> = env.open_database(&rwtxn, None).unwrap().unwrap();
+        db_main
+            .delete(&mut rwtxn, db_constants::KEY_INDEX_ID)
+            .unwrap();
+        rwtxn.commit().unwrap();
+        env.prepare_for_closing().wait();
+
+        let cache = Arc::new(SearchCache::new(1024));
+        let searcher = Searcher::<u32>::new(dir.path())
+            .unwrap()
+            .with_cache(cache.clone());
+        for _ in 0..2 {
+            let r = searcher.search::<NaiveIntersect>("look at my").0.unwrap();
+            assert_eq!(r, vec![0]);
+        }
+        assert!(cache.is_empty());
+        assert_eq!(cache.hits() + cache.misses(), 0);
+    }
+
+    #[test]
+    fn missing_merged_tokens_fall_back() {
+        let dir = TempDir::new("missing_merged");
+        let docs = vec![("to be or not to be", 0), ("be or to", 1), ("to be", 2)];
+        let common_tokens = ["to", "be", "or", "not"].map(String::from).into();
+        let indexer = Indexer::new(None, Some(CommonTokens::List(common_tokens)));
+        let (searcher, _) = indexer.index(docs, dir.path()).unwrap();
+        let queries = ["to be or not", "to be", "be or to", "not to be"];
+        let expected: Vec<_> = queries
+            .iter()
+            .map(|q| searcher.search::<NaiveIntersect>(q).0.unwrap())
+            .collect();
+        assert_eq!(expected[0], [0]);
+        drop(searcher);
+
+        // e.g. the index was built with other common tokens
+        let env = unsafe { EnvOpenOptions::new().max_dbs(3).open(dir.path()).unwrap() };
+        let mut rwtxn = env.write_txn().unwrap();
+        let db: Database<Str, Bytes> = env
+            .open_database(&rwtxn, Some(db_constants::DB_TOKEN_TO_OFFSETS))
+            .unwrap()
+            .unwrap();
+        let merged: Vec<String> = db
+            .iter(&rwtxn)
+            .unwrap()
+            .map(|entry| entry.unwrap().0.to_string())
+            .filter(|token| token.contains(' '))
+            .collect();
+        assert!(!merged.is_empty());
+        for token in merged.iter() {
+            db.delete(&mut rwtxn, token).unwrap();
+        }
+        rwtxn.commit().unwrap();
+        env.prepare_for_closing().wait();
+
+        let searcher = Searcher::<u32>::new(dir.path()).unwrap();
+        for (q, expected) in queries.iter().zip(expected) {
+            assert_eq!(searcher.search::<NaiveIntersect>(q).0.unwrap(), expected);
+            let explain = searcher.explain::<NaiveIntersect>(q).unwrap();
+            assert!(explain.final_tokens.iter().all(|t| t.num_merged == 1));
+        }
+        // only the missing single tokens fail
+        assert!(matches!(
+            searcher.search::<NaiveIntersect>("to be or nothing").0,
+            Err(SearchError::TokenNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn window_len_is_saved() {
+        let dir = TempDir::new("window_len");
+        let docs = vec![("to be or not to be", 0), ("to be or", 1)];
+        let common_tokens = ["to", "be", "or", "not"].map(String::from).into();
+        let indexer = Indexer::new(None, Some(CommonTokens::List(common_tokens)))
+            .with_window_len(NonZero::new(6).unwrap());
+        let (searcher, _) = indexer.index(docs, dir.path()).unwrap();
+        drop(searcher);
+
+        let merged = |searcher: &Searcher<u32>| {
+            let explain = searcher
+                .explain::<NaiveIntersect>("to be or not to be")
+                .unwrap();
+            assert_eq!(explain.num_documents, 1);
+            let final_tokens = explain.final_tokens.iter();
+            final_tokens
+                .map(|token| token.num_merged)
+                .collect::<Vec<_>>()
+        };
+        let searcher = Searcher::<u32>::new(dir.path()).unwrap();
+        assert_eq!(merged(&searcher), [6]);
+        drop(searcher);
+
+        // indexes generated before the window length was saved use the default
+        let env = unsafe { EnvOpenOptions::new().max_dbs(3).open(dir.path()).unwrap() };
+        let mut rwtxn = env.write_txn().unwrap();
+        let db_main: Database<Str, Unspecified> = env.open_database(&rwtxn, None).unwrap().unwrap();
+        db_main
+            .delete(&mut rwtxn, db_constants::KEY_WINDOW_LEN)
+            .unwrap();
+        rwtxn.commit().unwrap();
+        env.prepare_for_closing().wait();
+
+        let searcher = Searcher::<u32>::new(dir.path()).unwrap();
+        assert_eq!(merged(&searcher), [3, 3]);
+    }
+}
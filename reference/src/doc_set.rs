@@ -0,0 +1,248 @@
+use std::ops::Deref;
+
+#[cfg(target_feature = "avx512f")]
+use std::{
+    arch::x86_64::_mm512_mask_compressstoreu_epi32,
+    simd::{Simd, cmp::SimdPartialEq},
+};
+
+/// Ordered set of internal document IDs.
+///
+/// Used to combine the results of different searches, e.g.
+/// "search within results" or excluding a saved search.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct DocSet(Vec<u32>);
+
+impl Deref for DocSet {
+    type Target = [u32];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Vec<u32>> for DocSet {
+    /// Sorts and removes the duplicates of `doc_ids`.
+    fn from(mut doc_ids: Vec<u32>) -> Self {
+        if !doc_ids.is_sorted_by(|a, b| a < b) {
+            doc_ids.sort_unstable();
+            doc_ids.dedup();
+        }
+        Self(doc_ids)
+    }
+}
+
+impl FromIterator<u32> for DocSet {
+    fn from_iter<T: IntoIterator<Item = u32>>(iter: T) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl From<DocSet> for Vec<u32> {
+    fn from(set: DocSet) -> Self {
+        set.0
+    }
+}
+
+impl DocSet {
+    /// Creates a new set from document IDs that are already sorted
+    /// and without duplicates, like the ones returned by a search.
+    pub(crate) fn from_sorted(doc_ids: Vec<u32>) -> Self {
+        debug_assert!(doc_ids.is_sorted_by(|a, b| a < b));
+        Self(doc_ids)
+    }
+
+    /// Returns `true` if `doc_id` is in the set.
+    pub fn contains(&self, doc_id: u32) -> bool {
+        self.0.binary_search(&doc_id).is_ok()
+    }
+
+    /// Document IDs that are in both sets.
+    pub fn intersection(&self, other: &DocSet) -> DocSet {
+        Self(merge::<false>(&self.0, &other.0))
+    }
+
+    /// Document IDs that are in `self`, but not in `other`.
+    pub fn difference(&self, other: &DocSet) -> DocSet {
+        Self(merge::<true>(&self.0, &other.0))
+    }
+
+    /// Document IDs that are in any of the sets.
+    ///
+    /// Unlike [Self::intersection] and [Self::difference] this doesn't use
+    /// SIMD. Every element of both sets is written to the result, so the cost
+    /// is dominated by the writes and there are no elements to skip in bulk.
+    pub fn union(&self, other: &DocSet) -> DocSet {
+        let (lhs, rhs) = (&self.0, &other.0);
+        let mut doc_ids = Vec::with_capacity(lhs.len() + rhs.len());
+        let mut i = 0;
+        let mut j = 0;
+        while i < lhs.len() && j < rhs.len() {
+            match lhs[i].cmp(&rhs[j]) {
+                std::cmp::Ordering::Less => {
+                    doc_ids.push(lhs[i]);
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    doc_ids.push(rhs[j]);
+                    j += 1;
+                }
+                std::cmp::Ordering::Equal => {
+                    doc_ids.push(lhs[i]);
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        doc_ids.extend_from_slice(&lhs[i..]);
+        doc_ids.extend_from_slice(&rhs[j..]);
+        Self(doc_ids)
+    }
+}
+
+/// Merges the elements of `lhs` with `rhs`, keeping the ones that
+/// are also in `rhs` or the ones that are not in `rhs` if `DIFFERENCE`.
+#[cfg(not(target_feature = "avx512f"))]
+#[inline(always)]
+fn merge<const DIFFERENCE: bool>(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+    let mut doc_ids = Vec::with_capacity(lhs.len());
+    let mut j = 0;
+    for doc_id in lhs.iter().copied() {
+        while j < rhs.len() && rhs[j] < doc_id {
+            j += 1;
+        }
+        let found = j < rhs.len() && rhs[j] == doc_id;
+        if found != DIFFERENCE {
+            doc_ids.push(doc_id);
+        }
+    }
+    doc_ids
+}
+
+/// Merges the elements of `lhs` with `rhs`, keeping the ones that
+/// are also in `rhs` or the ones that are not in `rhs` if `DIFFERENCE`.
+///
+/// Compares blocks of 16 document IDs at a time, a block of `lhs`
+/// is only written when all of the blocks of `rhs` that could
+/// contain its elements were compared.
+#[cfg(target_feature = "avx512f")]
+#[inline(always)]
+fn merge<const DIFFERENCE: bool>(lhs: &[u32], rhs: &[u32]) -> Vec<u32> {
+    const N: usize = 16;
+
+    /// Which elements of `lhs` are in `rhs`, by comparing
+    /// `lhs` with all of the rotations of `rhs`.
+    #[inline(always)]
+    fn membership(lhs: Simd<u32, N>, mut rhs: Simd<u32, N>) -> u16 {
+        let mut mask = lhs.simd_eq(rhs).to_bitmask();
+        for _ in 1..N {
+            rhs = rhs.rotate_elements_left::<1>();
+            mask |= lhs.simd_eq(rhs).to_bitmask();
+        }
+        mask as u16
+    }
+
+    let mut doc_ids: Vec<u32> = Vec::with_capacity(lhs.len());
+    let mut len = 0;
+    let mut i = 0;
+    let mut j = 0;
+    // elements of the current block of `lhs` found so far
+    let mut mask = 0u16;
+    while i + N <= lhs.len() && j + N <= rhs.len() {
+        let lhs_block = Simd::from_slice(&lhs[i..i + N]);
+        let rhs_block = Simd::from_slice(&rhs[j..j + N]);
+        mask |= membership(lhs_block, rhs_block);
+
+        let lhs_last = lhs[i + N - 1];
+        let rhs_last = rhs[j + N - 1];
+        if lhs_last <= rhs_last {
+            let keep = if DIFFERENCE { !mask } else { mask };
+            unsafe {
+                _mm512_mask_compressstoreu_epi32(
+                    doc_ids.as_mut_ptr().add(len) as *mut _,
+                    keep,
+                    lhs_block.into(),
+                );
+            }
+            len += keep.count_ones() as usize;
+            i += N;
+            mask = 0;
+        }
+        if rhs_last <= lhs_last {
+            j += N;
+        }
+    }
+    unsafe { doc_ids.set_len(len) };
+
+    // the begining of the remaining elements may be part
+    // of a block that was already partially compared
+    for (k, doc_id) in lhs[i..].iter().copied().enumerate() {
+        while j < rhs.len() && rhs[j] < doc_id {
+            j += 1;
+        }
+        let found = (k < N && (mask >> k) & 1 == 1) || (j < rhs.len() && rhs[j] == doc_id);
+        if found != DIFFERENCE {
+            doc_ids.push(doc_id);
+        }
+    }
+
+    doc_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{DocSet, merge};
+    use crate::test_utils::Rng;
+
+    fn sorted(rng: &mut Rng, len: usize, max: u64) -> Vec<u32> {
+        let set: BTreeSet<u32> = (0..len).map(|_| rng.below(max) as u32).collect();
+        set.into_iter().collect()
+    }
+
+    fn check(lhs: &[u32], rhs: &[u32]) {
+        let lhs_set: BTreeSet<u32> = lhs.iter().copied().collect();
+        let rhs_set: BTreeSet<u32> = rhs.iter().copied().collect();
+        let intersection: Vec<u32> = lhs_set.intersection(&rhs_set).copied().collect();
+        let difference: Vec<u32> = lhs_set.difference(&rhs_set).copied().collect();
+        let union: Vec<u32> = lhs_set.union(&rhs_set).copied().collect();
+
+        assert_eq!(merge::<false>(lhs, rhs), intersection, "{lhs:?} {rhs:?}");
+        assert_eq!(merge::<true>(lhs, rhs), difference, "{lhs:?} {rhs:?}");
+
+        let lhs = DocSet::from(lhs.to_vec());
+        let rhs = DocSet::from(rhs.to_vec());
+        assert_eq!(*lhs.union(&rhs), union, "{lhs:?} {rhs:?}");
+    }
+
+    #[test]
+    fn merge_matches_btree_set() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let lhs_len = rng.below(100) as usize;
+            let rhs_len = rng.below(100) as usize;
+            // small ranges produce dense overlaps, big ones sparse
+            let max = 1 + rng.below(400);
+            let lhs = sorted(&mut rng, lhs_len, max);
+            let rhs = sorted(&mut rng, rhs_len, max);
+            check(&lhs, &rhs);
+        }
+    }
+
+    #[test]
+    fn merge_edge_cases() {
+        let block: Vec<u32> = (0..16).collect();
+        let odd: Vec<u32> = (1..16).step_by(2).collect();
+        check(&[], &[]);
+        check(&[], &block);
+        check(&block, &[]);
+        // all of the values in a single block
+        check(&block, &block);
+        check(&block, &odd);
+        check(&odd, &block);
+        check(&[7], &[7]);
+        check(&[7], &[8]);
+        check(&[u32::MAX], &[0, u32::MAX]);
+    }
+}
@@ -0,0 +1,136 @@
+use heed::RoTxn;
+
+use crate::{DB, DbError, db::Document, error::GetDocumentError};
+use rkyv::{Archive, Deserialize, de::Pool, rancor::Strategy};
+
+/// Consistent view of the documents in the database.
+///
+/// Owns a read transaction, so the archived documents can be borrowed
+/// from it without copying or using a callback. Multiple lookups can be
+/// made with the same snapshot and it can be sent between threads
+/// (e.g. held across `.await` points).
+///
+/// Avoid keeping it alive for too long, since the read
+/// transaction prevents the database from reusing pages.
+///
+/// Created by [crate::Searcher::snapshot].
+pub struct Snapshot<'a, D: Document> {
+    db: &'a DB<D>,
+    rotxn: RoTxn<'a>,
+}
+
+impl<'a, D: Document> Snapshot<'a, D> {
+    pub(crate) fn new(db: &'a DB<D>) -> Result<Self, DbError> {
+        let rotxn = db.env.read_txn()?;
+        Ok(Self { db, rotxn })
+    }
+
+    /// Gets the archived version of a document.
+    pub fn get(&self, doc_id: u32) -> Result<&D::Archived, GetDocumentError> {
+        self.db.inner_get_archived_document(&self.rotxn, &doc_id)
+    }
+
+    /// Gets the archived version of the documents.
+    pub fn get_many(&self, doc_ids: &[u32]) -> Result<Vec<&D::Archived>, GetDocumentError> {
+        doc_ids.iter().map(|doc_id| self.get(*doc_id)).collect()
+    }
+
+    /// Iterates over the internal document IDs and the archived
+    /// version of the documents, each document is only retrieved
+    /// when the iterator reaches it.
+    pub fn iter<'g>(&'g self, doc_ids: &'g [u32]) -> ArchivedDocumentsIter<'g, 'a, D> {
+        ArchivedDocumentsIter {
+            snapshot: self,
+            doc_ids: doc_ids.iter(),
+        }
+    }
+
+    /// Gets the deserialized version of a document.
+    pub fn get_document(&self, doc_id: u32) -> Result<D, GetDocumentError>
+    where
+        <D as Archive>::Archived: Deserialize<D, Strategy<Pool, rkyv::rancor::Error>>,
+    {
+        let archived = self.get(doc_id)?;
+        rkyv::deserialize::<D, rkyv::rancor::Error>(archived)
+            .map_err(|e| GetDocumentError::DbError(DbError::from(e)))
+    }
+}
+
+/// Archived documents that are retrieved lazily.
+///
+/// Holds a [Snapshot] for as long as it's alive, so the
+/// archived documents can be borrowed from it without copying.
+///
+/// Created by [crate::SearchResult::archived_documents] or
+/// [crate::Searcher::archived_documents].
+pub struct ArchivedDocuments<'a, D: Document> {
+    snapshot: Snapshot<'a, D>,
+    doc_ids: &'a [u32],
+}
+
+impl<'a, D: Document> ArchivedDocuments<'a, D> {
+    pub(crate) fn new(db: &'a DB<D>, doc_ids: &'a [u32]) -> Result<Self, DbError> {
+        Ok(Self {
+            snapshot: Snapshot::new(db)?,
+            doc_ids,
+        })
+    }
+
+    /// Snapshot used to retrieve the documents.
+    pub fn snapshot(&self) -> &Snapshot<'a, D> {
+        &self.snapshot
+    }
+
+    /// Internal document IDs of the documents.
+    pub fn doc_ids(&self) -> &'a [u32] {
+        self.doc_ids
+    }
+
+    /// Number of documents.
+    pub fn len(&self) -> usize {
+        self.doc_ids.len()
+    }
+
+    /// Returns `true` if there are no documents.
+    pub fn is_empty(&self) -> bool {
+        self.doc_ids.is_empty()
+    }
+
+    /// Iterates over the internal document IDs and the archived
+    /// version of the documents, each document is only retrieved
+    /// when the iterator reaches it.
+    pub fn iter(&self) -> ArchivedDocumentsIter<'_, 'a, D> {
+        self.snapshot.iter(self.doc_ids)
+    }
+}
+
+impl<'g, 'a, D: Document> IntoIterator for &'g ArchivedDocuments<'a, D> {
+    type Item = Result<(u32, &'g D::Archived), GetDocumentError>;
+    type IntoIter = ArchivedDocumentsIter<'g, 'a, D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over archived documents, created by
+/// [Snapshot::iter] or [ArchivedDocuments::iter].
+pub struct ArchivedDocumentsIter<'g, 'a, D: Document> {
+    snapshot: &'g Snapshot<'a, D>,
+    doc_ids: std::slice::Iter<'g, u32>,
+}
+
+impl<'g, D: Document> Iterator for ArchivedDocumentsIter<'g, '_, D> {
+    type Item = Result<(u32, &'g D::Archived), GetDocumentError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let doc_id = *self.doc_ids.next()?;
+        Some(self.snapshot.get(doc_id).map(|doc| (doc_id, doc)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.doc_ids.size_hint()
+    }
+}
+
+impl<D: Document> ExactSizeIterator for ArchivedDocumentsIter<'_, '_, D> {}
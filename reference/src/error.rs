@@ -17,6 +17,9 @@ pub enum DbError {
 
     #[error("Key `{0}` not found in database `{1}`")]
     KeyNotFound(String, String),
+
+    #[error("Index requires the `{0}` feature to be enabled")]
+    FeatureNotEnabled(String),
 }
 
 /// Possible errors that can occur while searching.
@@ -37,6 +40,25 @@ pub enum SearchError {
     #[error("Empty Intersection")]
     EmptyIntersection,
 
+    /// The deadline passed before the search finished.
+    ///
+    /// If some intersection was already performed, contains the documents
+    /// that matched part of the query. This is a superset of the final result.
+    #[error("Deadline exceeded while searching")]
+    DeadlineExceeded(Option<Vec<u32>>),
+
+    #[error("Search was cancelled")]
+    Cancelled,
+
+    #[error("Query has {0} tokens, but the maximum allowed is {1}")]
+    TooManyTokens(usize, usize),
+
+    #[error("Intermediate result could have {0} elements, but the maximum allowed is {1}")]
+    IntermediateTooBig(usize, usize),
+
+    #[error("Query expands into {0} synonym paths, but the maximum allowed is {1}")]
+    TooManyExpansions(usize, usize),
+
     #[error("Catastrophic error has occurred")]
     InternalError,
 }
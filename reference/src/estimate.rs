@@ -0,0 +1,34 @@
+/// Estimated size of an index, extrapolated from a sample of the corpus.
+///
+/// The sizes don't include the free space of the LMDB pages,
+/// so the files on disk are usually bigger.
+///
+/// Returned by [crate::Indexer::estimate].
+#[derive(Clone, Debug)]
+pub struct IndexEstimate {
+    /// Number of documents in the sample.
+    pub sample_docs: u64,
+    /// Number of documents in the corpus.
+    pub total_docs: u64,
+    /// Common tokens generated from the sample.
+    pub num_common_tokens: usize,
+    /// Distinct tokens, including the merged ones, estimated with
+    /// a HyperLogLog and extrapolated with Heaps' law.
+    pub distinct_tokens: u64,
+    /// Exponent of Heaps' law fitted to the sample, the number of distinct
+    /// tokens grows with the number of documents to the power of this.
+    pub heaps_exponent: f64,
+    /// Size in bytes of the `roaringish_packed` file.
+    pub roaringish_packed_bytes: u64,
+    /// Size in bytes of the stored documents.
+    pub documents_bytes: u64,
+    /// Size in bytes of the table from tokens to their Roaringish Packed.
+    pub token_table_bytes: u64,
+}
+
+impl IndexEstimate {
+    /// Total size in bytes of the index.
+    pub fn total_bytes(&self) -> u64 {
+        self.roaringish_packed_bytes + self.documents_bytes + self.token_table_bytes
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use crate::IntersectionAlgorithm;
+
+/// Token chosen by the merge and minimize phase.
+#[derive(Clone, Debug)]
+pub struct ExplainToken {
+    /// Token as stored in the database, merged tokens are separated by a space
+    /// and the alternatives of a position expanded by the synonyms by a `|`.
+    pub token: String,
+    /// Number of query tokens that were merged into this token.
+    pub num_merged: usize,
+    /// Number of elements in the Roaringish Packed of this token.
+    pub packed_len: usize,
+}
+
+/// Single intersection performed while searching.
+#[derive(Clone, Debug)]
+pub struct ExplainStep {
+    /// Tokens on the left hand side of the intersection.
+    pub lhs: String,
+    /// Tokens on the right hand side of the intersection.
+    pub rhs: String,
+    /// Distance (in tokens) between the begining of `lhs` and `rhs`.
+    pub lhs_len: u32,
+    /// Number of elements in the Roaringish Packed of the left hand side.
+    pub lhs_packed_len: usize,
+    /// Number of elements in the Roaringish Packed of the right hand side.
+    pub rhs_packed_len: usize,
+    /// Algorithm used in the first phase of the intersection.
+    ///
+    /// [None] if one of the sides is empty.
+    pub first_phase: Option<IntersectionAlgorithm>,
+    /// Algorithm used in the second phase of the intersection.
+    ///
+    /// [None] if one of the sides is empty.
+    pub second_phase: Option<IntersectionAlgorithm>,
+    /// Number of elements in the resulting Roaringish Packed.
+    pub result_len: usize,
+    /// Time spent in this intersection.
+    pub elapsed: Duration,
+}
+
+/// Explanation of how a query was planned and executed.
+///
+/// Returned by [crate::Searcher::explain].
+#[derive(Clone, Debug)]
+pub struct Explain {
+    /// Query tokens after normalization and tokenization, the alternatives
+    /// of a position expanded by the synonyms are separated by a `|`.
+    pub tokens: Vec<String>,
+    /// Tokens chosen by the merge and minimize phase, in query order.
+    pub final_tokens: Vec<ExplainToken>,
+    /// Intersections in the order they were performed.
+    ///
+    /// If the intersection becomes empty the search stops early,
+    /// so the last step will have a `result_len` of 0.
+    pub steps: Vec<ExplainStep>,
+    /// Number of documents that matched the query.
+    pub num_documents: usize,
+    /// Plans of the other expansions of the query by the synonyms, that
+    /// need their own search, e.g. because their synonyms have a different
+    /// number of tokens. The result is the union of all of the plans.
+    pub alternatives: Vec<Explain>,
+}
@@ -1,17 +1,22 @@
-use std::{cmp::Reverse, collections::HashSet, path::Path};
+use std::{collections::HashSet, num::NonZero, path::Path};
 
 use crate::{
-    RoaringishPacked, Searcher,
-    db::{DB, Document, MAX_WINDOW_LEN},
+    Aliases, Analyzer, RoaringishPacked, Searcher,
+    analyzer::Token,
+    chunks::{CHUNK_OVERLAP, CHUNK_STEP, Chunks},
+    db::{DB, DEFAULT_WINDOW_LEN, Document},
     decreasing_window_iter::DecreasingWindows,
     error::DbError,
+    estimate::IndexEstimate,
+    phrases::Phrases,
+    query_log::{QueryLog, QueryLogReport},
     roaringish::MAX_VALUE,
-    utils::{normalize, tokenize},
+    token_counter::TokenCounter,
 };
 use fxhash::FxHashMap;
 use gxhash::{HashMap as GxHashMap, HashMapExt};
-use heed::RwTxn;
 use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+use rkyv::{Archive, Deserialize, Serialize};
 
 /// Specifies how the common tokens are treated during indexing.
 #[derive(Debug)]
@@ -22,6 +27,33 @@ pub enum CommonTokens {
     FixedNum(u32),
     /// Percentage of the top `n` most frequent tokens.
     Percentage(f64),
+    /// Tokens that appear in at least this fraction of the documents,
+    /// e.g. `0.1` for the tokens in 10% of the documents.
+    MinDocFrequency(f64),
+    /// Up to `max` tokens that most reduce the estimated cost of searching
+    /// the `queries`, instead of simply the most frequent ones.
+    ///
+    /// The cost of each query is estimated with the frequency of its tokens
+    /// and merged tokens. Use [Indexer::replay_query_log] to see the
+    /// predicted gains.
+    QueryLog { queries: Vec<String>, max: u32 },
+}
+
+/// Maximum number of tokens tracked when counting the tokens of the corpus.
+const CORPUS_COUNTER_CAPACITY: usize = 1 << 18;
+
+/// Token id of the positions that don't have a token, e.g.
+/// the punctuation dropped by [crate::Punctuation::Gap].
+const GAP_TOKEN_ID: u32 = u32::MAX;
+
+/// Tokenized documents of a batch, written to disk by [Indexer::index]
+/// until the common tokens of all of the batches are known.
+#[derive(Archive, Serialize, Deserialize)]
+pub(crate) struct TokenizedBatch {
+    tokens: Vec<Box<str>>,
+    doc_ids: Vec<u32>,
+    docs: Vec<Vec<u32>>,
+    aliases: Vec<Vec<(u32, u32)>>,
 }
 
 /// Batch of documents to be indexed.
@@ -48,26 +80,38 @@ struct Batch<D: Document> {
     /// This should be in sync with `token_id_to_roaringish_packed`
     token_id_to_token: Vec<Box<str>>,
 
-    // this 3 containers are in sync
     /// Document ids in the batch (cleared after each batch).
     ///
-    /// This should be in sync with `documents` and `tokenized_docs`.
+    /// This should be in sync with `documents`.
     doc_ids: Vec<u32>,
     /// Documents in the batch (cleared after each batch).
     ///
-    /// This should be in sync with `doc_ids` and `tokenized_docs`.
+    /// This should be in sync with `doc_ids`.
     documents: Vec<D>,
 
+    /// Ids of the tokenized documents in the batch, long documents
+    /// have one per chunk (cleared after each batch).
+    ///
+    /// This should be in sync with `tokenized_docs`.
+    tokenized_doc_ids: Vec<u32>,
     /// Tokenized representation of the documents in the batch (cleared after each batch).
     /// This representation is done by storing the token id.
     ///
-    /// This should be in sync with `doc_ids` and `documents`.
+    /// This should be in sync with `tokenized_doc_ids`.
     tokenized_docs: Vec<Vec<u32>>,
+    /// Positions and token ids of the aliases of each tokenized
+    /// document in the batch (cleared after each batch).
+    ///
+    /// This should be in sync with `tokenized_docs`.
+    tokenized_aliases: Vec<Vec<(u32, u32)>>,
+
+    /// Maximum number of tokens merged into a single token.
+    window_len: NonZero<usize>,
 }
 
 impl<D: Document> Batch<D> {
     /// Constructs a new batch.
-    fn new() -> Self {
+    fn new(window_len: NonZero<usize>) -> Self {
         Self {
             batch_id: 0,
             // This can't fail
@@ -78,7 +122,10 @@ impl<D: Document> Batch<D> {
             token_id_to_token: Vec::new(),
             doc_ids: Vec::new(),
             documents: Vec::new(),
+            tokenized_doc_ids: Vec::new(),
             tokenized_docs: Vec::new(),
+            tokenized_aliases: Vec::new(),
+            window_len,
         }
     }
 
@@ -98,18 +145,61 @@ impl<D: Document> Batch<D> {
 
         self.doc_ids.clear();
         self.documents.clear();
+        self.tokenized_doc_ids.clear();
         self.tokenized_docs.clear();
+        self.tokenized_aliases.clear();
     }
 
     /// Adds a document to the batch and starts the indexing process.
     ///
-    /// `count_freq` is used to count the frequency of each token. This should
-    /// only be used in the first batch, allowing us to generate the common tokens.
-    fn push(&mut self, doc_id: u32, content: &str, doc: D, count_freq: impl FnMut(&str)) {
-        let tokenized_doc = self.index_doc(content, doc_id, count_freq);
+    /// Documents with more than [MAX_VALUE] positions are split into
+    /// overlapping chunks, that use the ids after `doc_id` and are added
+    /// to `chunks`. Returns the next free document id.
+    ///
+    /// `count_freq` is used to count the frequency of each token,
+    /// allowing us to generate the common tokens.
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        doc_id: u32,
+        content: &str,
+        doc: D,
+        aliases: &Aliases,
+        analyzer: &Analyzer,
+        chunks: &mut Chunks,
+        mut count_freq: impl FnMut(&str),
+    ) -> u32 {
+        let content = analyzer.normalize(content);
+        // the chunks overlap, so the content is only tokenized once
+        let tokens: Vec<Token> = analyzer.tokenize(&content, false).collect();
+        let mut start = 0;
+        let mut chunk_id = doc_id;
+        loop {
+            let i = tokens.partition_point(|t| t.pos < start);
+            let (tokenized_doc, tokenized_aliases, truncated) = self.index_doc(
+                &tokens[i..],
+                start,
+                chunk_id,
+                aliases,
+                analyzer,
+                &mut count_freq,
+            );
+            self.tokenized_doc_ids.push(chunk_id);
+            self.tokenized_docs.push(tokenized_doc);
+            self.tokenized_aliases.push(tokenized_aliases);
+            if !truncated {
+                break;
+            }
+
+            start += CHUNK_STEP;
+            chunk_id += 1;
+            chunks.push(chunk_id, doc_id);
+            log::debug!("Indexing chunk {chunk_id} of document {doc_id} at position {start}");
+        }
+
         self.doc_ids.push(doc_id);
         self.documents.push(doc);
-        self.tokenized_docs.push(tokenized_doc);
+        chunk_id + 1
     }
 
     /// Get the token id for the input `token`. If the token is not present in the
@@ -142,20 +232,34 @@ impl<D: Document> Batch<D> {
         *token_id
     }
 
-    /// Indexes `content`s for this `doc_id`.
+    /// Indexes the `tokens` of a document for this `doc_id`, starting at
+    /// the position `start`. Returns the tokenized representation of the
+    /// document, the positions and token ids of the aliases and `true` if
+    /// it was truncated at [MAX_VALUE] positions.
+    ///
+    /// The `analyzer` transforms the tokens, the tokens that it stacks
+    /// and the `aliases` of each token are indexed at the same position
+    /// as the token, but they are not part of the tokenized representation
+    /// of the document.
     ///
-    /// `count_freq` is used to count the frequency of each token. This should
-    /// only be used in the first batch, allowing us to generate the common tokens.
+    /// `count_freq` is used to count the frequency of each token,
+    /// allowing us to generate the common tokens.
     fn index_doc(
         &mut self,
-        content: &str,
+        tokens: &[Token],
+        start: u32,
         doc_id: u32,
+        aliases: &Aliases,
+        analyzer: &Analyzer,
         mut count_freq: impl FnMut(&str),
-    ) -> Vec<u32> {
+    ) -> (Vec<u32>, Vec<(u32, u32)>, bool) {
         let mut tokenized_doc = Vec::new();
+        let mut tokenized_aliases = Vec::new();
         let mut token_id_to_positions: FxHashMap<u32, Vec<u32>> = FxHashMap::new();
-        let content = normalize(content);
-        for (pos, token) in tokenize(&content).enumerate().take(MAX_VALUE as usize) {
+        let mut has_aliases = false;
+        let mut truncated = false;
+
+        let mut index_token = |token: &str, pos: u32| {
             let token_id = Self::get_token_id(
                 token,
                 &mut self.hllp_tokens,
@@ -164,28 +268,92 @@ impl<D: Document> Batch<D> {
                 &mut self.token_id_to_roaringish_packed,
                 &mut self.next_token_id,
             );
+            token_id_to_positions.entry(token_id).or_default().push(pos);
+            token_id
+        };
+
+        for Token {
+            pos,
+            token: original,
+            stacked,
+            ..
+        } in tokens
+        {
+            let pos = pos - start;
+            if pos >= MAX_VALUE {
+                truncated = true;
+                break;
+            }
 
-            count_freq(token);
+            // the positions that were skipped can't be merged
+            tokenized_doc.resize(pos as usize, GAP_TOKEN_ID);
 
-            token_id_to_positions
-                .entry(token_id)
-                .or_default()
-                .push(pos as u32);
+            let (token, stem) = analyzer.index_token(original);
+            let token_id = index_token(&token, pos);
+            // the overlap was already counted by the previous chunk
+            if start == 0 || pos >= CHUNK_OVERLAP {
+                count_freq(&token);
+            }
             tokenized_doc.push(token_id);
+
+            if let Some(stem) = stem {
+                index_token(&stem, pos);
+            }
+            if let Some(stacked) = stacked {
+                let (stacked, stem) = analyzer.index_token(stacked);
+                index_token(&stacked, pos);
+                if let Some(stem) = stem {
+                    index_token(&stem, pos);
+                }
+            }
+
+            if aliases.is_empty() {
+                continue;
+            }
+
+            // identifiers of source code have aliases as a whole
+            let stacked_aliases = stacked.iter().flat_map(|stacked| aliases.get(stacked));
+            for alias in aliases.get(original).iter().chain(stacked_aliases) {
+                for alias_token in alias.iter() {
+                    let pos = pos + alias_token.offset;
+                    if pos >= MAX_VALUE {
+                        break;
+                    }
+
+                    let (token, stem) = analyzer.index_token(&alias_token.token);
+                    let token_id = index_token(&token, pos);
+                    if alias_token.mergeable {
+                        tokenized_aliases.push((pos, token_id));
+                    }
+                    if let Some(stem) = stem {
+                        index_token(&stem, pos);
+                    }
+                    has_aliases = true;
+                }
+            }
         }
 
-        for (token_id, positions) in token_id_to_positions.iter() {
+        for (token_id, positions) in token_id_to_positions.iter_mut() {
+            // aliases can be pushed out of order
+            if has_aliases {
+                positions.sort_unstable();
+                positions.dedup();
+            }
             self.token_id_to_roaringish_packed[*token_id as usize].push(doc_id, positions);
         }
-        tokenized_doc
+        (tokenized_doc, tokenized_aliases, truncated)
     }
 
     /// Flushes the batch.
+    ///
+    /// If the `common_tokens` aren't known yet the tokens aren't merged,
+    /// the tokenized documents are written to disk instead and merged
+    /// later by [Self::flush_tokenized].
     fn flush(
         &mut self,
         db: &DB<D>,
-        rwtxn: &mut RwTxn,
-        common_tokens: &HashSet<Box<str>>,
+        common_tokens: Option<&HashSet<Box<str>>>,
+        phrases: &Phrases,
         mmap_size: &mut usize,
     ) -> Result<(), DbError> {
         log::info!("Flushing batch");
@@ -197,7 +365,9 @@ impl<D: Document> Batch<D> {
             return Ok(());
         }
 
-        self.merge_common_tokens(common_tokens);
+        if let Some(common_tokens) = common_tokens {
+            self.merge_common_tokens(common_tokens, phrases);
+        }
 
         db.write_token_to_roaringish_packed(
             &self.token_to_token_id,
@@ -205,7 +375,12 @@ impl<D: Document> Batch<D> {
             mmap_size,
             self.batch_id,
         )?;
-        db.write_doc_id_to_document(rwtxn, &self.doc_ids, &self.documents)?;
+        let documents = DB::serialize_documents(&self.documents)?;
+        db.reserve_map_size_for_documents(&documents)?;
+        db.write_txn(|rwtxn| db.write_doc_id_to_document(rwtxn, &self.doc_ids, &documents))?;
+        if common_tokens.is_none() {
+            db.write_tokenized_batch(&self.take_tokenized(), self.batch_id)?;
+        }
 
         self.batch_id += 1;
         self.clear();
@@ -213,10 +388,74 @@ impl<D: Document> Batch<D> {
         Ok(())
     }
 
+    /// Flushes the merged tokens of a batch flushed without the common
+    /// tokens, as a new batch.
+    fn flush_tokenized(
+        &mut self,
+        db: &DB<D>,
+        tokenized: TokenizedBatch,
+        common_tokens: &HashSet<Box<str>>,
+        phrases: &Phrases,
+        mmap_size: &mut usize,
+    ) -> Result<(), DbError> {
+        self.merge_tokenized(tokenized, common_tokens, phrases);
+        db.write_token_to_roaringish_packed(
+            &self.token_to_token_id,
+            &self.token_id_to_roaringish_packed,
+            mmap_size,
+            self.batch_id,
+        )?;
+
+        self.batch_id += 1;
+        self.clear();
+        Ok(())
+    }
+
+    /// Takes the tokenized documents of the batch, they can be
+    /// merged later by [Self::merge_tokenized].
+    fn take_tokenized(&mut self) -> TokenizedBatch {
+        TokenizedBatch {
+            tokens: std::mem::take(&mut self.token_id_to_token),
+            doc_ids: std::mem::take(&mut self.tokenized_doc_ids),
+            docs: std::mem::take(&mut self.tokenized_docs),
+            aliases: std::mem::take(&mut self.tokenized_aliases),
+        }
+    }
+
+    /// Replaces the batch with the `tokenized` documents and merges them,
+    /// only the merged tokens are kept, the others were already flushed.
+    fn merge_tokenized(
+        &mut self,
+        tokenized: TokenizedBatch,
+        common_tokens: &HashSet<Box<str>>,
+        phrases: &Phrases,
+    ) {
+        self.clear();
+        // the tokens get the same ids as before
+        for token in tokenized.tokens.iter() {
+            Self::get_token_id(
+                token,
+                &mut self.hllp_tokens,
+                &mut self.token_to_token_id,
+                &mut self.token_id_to_token,
+                &mut self.token_id_to_roaringish_packed,
+                &mut self.next_token_id,
+            );
+        }
+        self.tokenized_doc_ids = tokenized.doc_ids;
+        self.tokenized_docs = tokenized.docs;
+        self.tokenized_aliases = tokenized.aliases;
+
+        let num_tokens = self.next_token_id;
+        self.merge_common_tokens(common_tokens, phrases);
+        self.token_to_token_id
+            .retain(|_, token_id| *token_id >= num_tokens);
+    }
+
     /// Merges the tokens for all of the documents in the batch.
     /// This will create new tokens and consequently new token ids.
     ///
-    /// The generation is done by merging up to [MAX_WINDOW_LEN] tokens at a time.
+    /// The generation is done by merging up to `window_len` tokens at a time.
     /// We are only allowed to merge:
     /// * Common tokens with other common tokens.
     /// * Rare tokens with a common token.
@@ -236,23 +475,57 @@ impl<D: Document> Batch<D> {
     /// ```
     ///
     /// This will generate all possible combinations of the merging process.
-    fn merge_common_tokens(&mut self, common_tokens: &HashSet<Box<str>>) {
+    ///
+    /// The `phrases` are also merged, independently of the common tokens
+    /// and of the window length.
+    ///
+    /// The tokens of the aliases are merged the same way, together with the
+    /// tokens of the document and of the other aliases at the same positions.
+    fn merge_common_tokens(&mut self, common_tokens: &HashSet<Box<str>>, phrases: &Phrases) {
         log::debug!("Merging common tokens");
-        if common_tokens.is_empty() {
+        if common_tokens.is_empty() && phrases.is_empty() {
             return;
         }
 
+        // phrases with a token that isn't in the batch can't match
+        let mut first_token_id_to_phrases: FxHashMap<u32, Vec<(Vec<u32>, String)>> =
+            FxHashMap::new();
+        for tokens in phrases.iter() {
+            let token_ids: Option<Vec<u32>> = tokens
+                .iter()
+                .map(|token| self.token_to_token_id.get(token).copied())
+                .collect();
+            if let Some(token_ids) = token_ids {
+                first_token_id_to_phrases
+                    .entry(token_ids[0])
+                    .or_default()
+                    .push((token_ids, tokens.join(" ")));
+            }
+        }
+
         let b = std::time::Instant::now();
-        for (tokenized_doc, doc_id) in self.tokenized_docs.iter().zip(self.doc_ids.iter()) {
+        let window_len = self.window_len.get();
+        for ((tokenized_doc, tokenized_aliases), doc_id) in self
+            .tokenized_docs
+            .iter()
+            .zip(self.tokenized_aliases.iter())
+            .zip(self.tokenized_doc_ids.iter())
+        {
             let mut token_id_to_positions: FxHashMap<u32, Vec<u32>> = FxHashMap::new();
-            let it = DecreasingWindows::new(tokenized_doc, MAX_WINDOW_LEN);
+            let it = DecreasingWindows::new(tokenized_doc, self.window_len);
             for (pos, token_ids) in it.enumerate() {
                 let token_id = token_ids[0];
+                if token_id == GAP_TOKEN_ID {
+                    continue;
+                }
                 let token = &self.token_id_to_token[token_id as usize];
                 let is_first_token_rare = !common_tokens.contains(token);
 
                 for i in 1..token_ids.len() {
                     let token_id = token_ids[i];
+                    if token_id == GAP_TOKEN_ID {
+                        break;
+                    }
                     let token = &self.token_id_to_token[token_id as usize];
                     let is_token_rare = !common_tokens.contains(token);
                     if is_first_token_rare && is_token_rare {
@@ -279,6 +552,56 @@ impl<D: Document> Batch<D> {
                         break;
                     }
                 }
+
+                let Some(phrases) = first_token_id_to_phrases.get(&token_id) else {
+                    continue;
+                };
+                for (phrase_token_ids, phrase) in phrases {
+                    if !tokenized_doc[pos..].starts_with(phrase_token_ids) {
+                        continue;
+                    }
+                    let token_id = Self::get_token_id(
+                        phrase,
+                        &mut self.hllp_tokens,
+                        &mut self.token_to_token_id,
+                        &mut self.token_id_to_token,
+                        &mut self.token_id_to_roaringish_packed,
+                        &mut self.next_token_id,
+                    );
+                    // the phrase might also be merged common tokens
+                    let positions = token_id_to_positions.entry(token_id).or_default();
+                    if positions.last() != Some(&(pos as u32)) {
+                        positions.push(pos as u32);
+                    }
+                }
+            }
+
+            if !tokenized_aliases.is_empty() {
+                let merged = self.merge_aliases(
+                    tokenized_doc,
+                    tokenized_aliases,
+                    common_tokens,
+                    &first_token_id_to_phrases,
+                    window_len,
+                );
+                for (pos, token) in merged {
+                    let token_id = Self::get_token_id(
+                        &token,
+                        &mut self.hllp_tokens,
+                        &mut self.token_to_token_id,
+                        &mut self.token_id_to_token,
+                        &mut self.token_id_to_roaringish_packed,
+                        &mut self.next_token_id,
+                    );
+                    token_id_to_positions.entry(token_id).or_default().push(pos);
+                }
+
+                // the windows with aliases are pushed out of order and
+                // can repeat the windows of the document
+                for positions in token_id_to_positions.values_mut() {
+                    positions.sort_unstable();
+                    positions.dedup();
+                }
             }
 
             for (token_id, positions) in token_id_to_positions.iter() {
@@ -287,12 +610,113 @@ impl<D: Document> Batch<D> {
         }
         log::debug!("Merge took {:?}", b.elapsed());
     }
+
+    /// Merged tokens and phrases of a tokenized document that contain at
+    /// least one token of its `tokenized_aliases`, with their positions.
+    ///
+    /// Each position can have the token of the document and the tokens of
+    /// the aliases, so every combination of them follows the same rules as
+    /// [Self::merge_common_tokens].
+    fn merge_aliases(
+        &self,
+        tokenized_doc: &[u32],
+        tokenized_aliases: &[(u32, u32)],
+        common_tokens: &HashSet<Box<str>>,
+        first_token_id_to_phrases: &FxHashMap<u32, Vec<(Vec<u32>, String)>>,
+        window_len: usize,
+    ) -> Vec<(u32, String)> {
+        let mut alias_token_ids: FxHashMap<usize, Vec<u32>> = FxHashMap::new();
+        for (pos, token_id) in tokenized_aliases.iter().copied() {
+            let pos = pos as usize;
+            if tokenized_doc.get(pos) != Some(&token_id) {
+                alias_token_ids.entry(pos).or_default().push(token_id);
+            }
+        }
+
+        // tokens at `pos` and if they are from an alias
+        let tokens_at = |pos: usize| {
+            let token_id = tokenized_doc
+                .get(pos)
+                .copied()
+                .filter(|token_id| *token_id != GAP_TOKEN_ID);
+            let aliases = alias_token_ids.get(&pos).into_iter().flatten();
+            token_id
+                .map(|token_id| (token_id, false))
+                .into_iter()
+                .chain(aliases.map(|token_id| (*token_id, true)))
+        };
+        let is_common =
+            |token_id: u32| common_tokens.contains(&self.token_id_to_token[token_id as usize]);
+        let to_token = |token_ids: &[u32]| -> String {
+            token_ids
+                .iter()
+                .map(|token_id| self.token_id_to_token[*token_id as usize].as_ref())
+                .intersperse(" ")
+                .collect()
+        };
+
+        // only the windows that can reach an alias
+        let mut starts: Vec<usize> = alias_token_ids
+            .keys()
+            .flat_map(|pos| pos.saturating_sub(window_len - 1)..=*pos)
+            .collect();
+        starts.sort_unstable();
+        starts.dedup();
+
+        let mut merged = Vec::new();
+        for start in starts {
+            let mut windows: Vec<(Vec<u32>, bool)> = tokens_at(start)
+                .map(|(token_id, alias)| (vec![token_id], alias))
+                .collect();
+            while let Some((window, has_alias)) = windows.pop() {
+                if window.len() > 1 && has_alias {
+                    merged.push((start as u32, to_token(&window)));
+                }
+
+                // this can't fail, windows are never empty
+                let last = *window.last().unwrap();
+                if window.len() == window_len || (window.len() > 1 && !is_common(last)) {
+                    continue;
+                }
+
+                let is_first_token_rare = !is_common(window[0]);
+                for (token_id, alias) in tokens_at(start + window.len()) {
+                    if is_first_token_rare && !is_common(token_id) {
+                        continue;
+                    }
+                    let mut window = window.clone();
+                    window.push(token_id);
+                    windows.push((window, has_alias || alias));
+                }
+            }
+
+            for (token_id, _) in tokens_at(start) {
+                let Some(phrases) = first_token_id_to_phrases.get(&token_id) else {
+                    continue;
+                };
+                for (phrase_token_ids, phrase) in phrases {
+                    let matches = phrase_token_ids
+                        .iter()
+                        .enumerate()
+                        .all(|(i, token_id)| tokens_at(start + i).any(|(t, _)| t == *token_id));
+                    if matches {
+                        merged.push((start as u32, phrase.clone()));
+                    }
+                }
+            }
+        }
+        merged
+    }
 }
 
 /// Responsible for indexing documents.
 pub struct Indexer {
     batch_size: Option<u32>,
     common_tokens: Option<CommonTokens>,
+    aliases: Aliases,
+    analyzer: Analyzer,
+    window_len: NonZero<usize>,
+    phrases: Vec<String>,
 }
 
 impl Indexer {
@@ -304,41 +728,150 @@ impl Indexer {
         Self {
             batch_size,
             common_tokens,
+            aliases: Aliases::default(),
+            analyzer: Analyzer::default(),
+            window_len: DEFAULT_WINDOW_LEN,
+            phrases: Vec::new(),
+        }
+    }
+
+    /// Merges up to `window_len` common tokens into a single token, the
+    /// default is 3. It's saved in the index so the queries are merged
+    /// the same way.
+    ///
+    /// Longer windows make phrases with many common tokens, e.g.
+    /// "to be or not to be", faster to search, but the index is bigger.
+    pub fn with_window_len(mut self, window_len: NonZero<usize>) -> Self {
+        self.window_len = window_len;
+        self
+    }
+
+    /// Analyzes the tokens of the documents with `analyzer`, it's saved
+    /// in the index so the queries are analyzed the same way.
+    pub fn with_analyzer(mut self, analyzer: Analyzer) -> Self {
+        self.analyzer = analyzer;
+        self
+    }
+
+    /// Indexes the `aliases` of each token at the same position as the token.
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Indexes each of the `phrases` as a single merged token, e.g. product
+    /// names or named entities, so they are searched with a single lookup
+    /// regardless of the frequency of their tokens. They are saved in
+    /// the index so the queries use them.
+    ///
+    /// The phrases are normalized and tokenized the same way as the
+    /// documents, phrases with a single token are ignored with a warning.
+    pub fn with_phrases<S, I>(mut self, phrases: I) -> Self
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        self.phrases = phrases
+            .into_iter()
+            .map(|phrase| phrase.as_ref().to_string())
+            .collect();
+        self
+    }
+
+    /// Generates the common tokens from the token frequencies of all of
+    /// the `contents`, before indexing them.
+    ///
+    /// [Self::index] already counts all of the documents, but it can only
+    /// merge the batches once they are all counted, so it writes their
+    /// tokenized documents to disk until then. With this pre-pass, that
+    /// only tokenizes the documents, each batch is merged when it's flushed.
+    ///
+    /// The tokens are counted the same way as [Self::index], at most
+    /// 2^18 distinct tokens are tracked at a time, so the
+    /// frequencies of the rare tokens are approximated. The common tokens
+    /// are then used as a [CommonTokens::List]. Does nothing if the common
+    /// tokens are [None] or already a [CommonTokens::List].
+    pub fn with_corpus_common_tokens<S, I>(mut self, contents: I) -> Self
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        if !self.counts_common_tokens() {
+            return self;
+        }
+
+        let mut counter = self.token_counter(Some(CORPUS_COUNTER_CAPACITY));
+        self.count_corpus(&mut counter, contents);
+        let tokens = self
+            .generate_common_tokens(&mut counter)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        self.common_tokens = Some(CommonTokens::List(tokens));
+        self
+    }
+
+    /// Predicts the gains of the common tokens on the `queries`, by
+    /// replaying them with the token frequencies of all of the `contents`.
+    ///
+    /// The common tokens are generated the same way as
+    /// [Self::with_corpus_common_tokens], so different [CommonTokens]
+    /// can be compared, e.g. [CommonTokens::FixedNum] and
+    /// [CommonTokens::QueryLog] with the same `queries`.
+    pub fn replay_query_log<S, I, Q>(&self, contents: I, queries: &[Q]) -> QueryLogReport
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+        Q: AsRef<str>,
+    {
+        let log = QueryLog::new(queries, &self.analyzer, self.window_len);
+        let mut counter = self.token_counter(Some(CORPUS_COUNTER_CAPACITY));
+        log.track(&mut counter);
+        self.count_corpus(&mut counter, contents);
+
+        let common_tokens = self.generate_common_tokens(&mut counter);
+        log.report(&common_tokens, &counter)
+    }
+
+    /// If the common tokens are generated from the token frequencies.
+    fn counts_common_tokens(&self) -> bool {
+        !matches!(self.common_tokens, None | Some(CommonTokens::List(_)))
+    }
+
+    /// Creates the counter of the token frequencies, that also counts the
+    /// merged tokens of the queries of a [CommonTokens::QueryLog].
+    fn token_counter(&self, capacity: Option<usize>) -> TokenCounter {
+        let mut counter = TokenCounter::new(capacity, self.window_len);
+        if let Some(CommonTokens::QueryLog { queries, .. }) = &self.common_tokens {
+            QueryLog::new(queries, &self.analyzer, self.window_len).track(&mut counter);
+        }
+        counter
+    }
+
+    /// Counts the tokens of all of the `contents`, without indexing them.
+    fn count_corpus<S, I>(&self, counter: &mut TokenCounter, contents: I)
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        let b = std::time::Instant::now();
+        for content in contents {
+            counter.next_doc();
+            let content = self.analyzer.normalize(content.as_ref());
+            for Token { token, .. } in self.analyzer.tokenize(&content, false) {
+                let (token, _) = self.analyzer.index_token(&token);
+                counter.add(&token);
+            }
         }
+        log::info!("Counting the tokens of the corpus took {:?}", b.elapsed());
     }
 
     /// Generates the list of common tokens to be used
     /// in the merging phase
-    fn generate_common_tokens(
-        &self,
-        token_to_freq: &GxHashMap<Box<str>, u32>,
-    ) -> HashSet<Box<str>> {
-        let Some(common_tokens) = &self.common_tokens else {
-            return HashSet::new();
-        };
-        match common_tokens {
-            CommonTokens::List(tokens) => tokens
-                .into_iter()
-                .map(|t| t.to_string().clone().into_boxed_str())
-                .collect(),
-            CommonTokens::FixedNum(max) => {
-                let max = (*max as usize).min(token_to_freq.len());
-                let mut token_to_freq: Vec<_> = token_to_freq.iter().collect();
-                token_to_freq.sort_unstable_by_key(|(_, freq)| Reverse(*freq));
-                token_to_freq[0..max]
-                    .iter()
-                    .map(|(token, _)| (*token).clone())
-                    .collect()
-            }
-            CommonTokens::Percentage(p) => {
-                let max = (token_to_freq.len() as f64 * *p) as usize;
-                let mut token_to_freq: Vec<_> = token_to_freq.iter().collect();
-                token_to_freq.sort_unstable_by_key(|(_, freq)| Reverse(*freq));
-                token_to_freq[0..max]
-                    .iter()
-                    .map(|(token, _)| (*token).clone())
-                    .collect()
-            }
+    fn generate_common_tokens(&self, counter: &mut TokenCounter) -> HashSet<Box<str>> {
+        match &self.common_tokens {
+            Some(common_tokens) => counter.common_tokens(common_tokens, &self.analyzer),
+            None => HashSet::new(),
         }
     }
 
@@ -351,13 +884,18 @@ impl Indexer {
     ///
     /// The type `D` is anything that can be serialized by [rkyv].
     ///
+    /// Documents with more than [MAX_VALUE] tokens are indexed as overlapping
+    /// chunks, phrases with up to [CHUNK_OVERLAP] tokens match across them.
+    /// The chunks use the internal ids that follow the id of their document,
+    /// so the internal ids of the documents after it are shifted and aren't
+    /// their positions in `docs` anymore. The searches always return the
+    /// ids of the documents, never the ones of the chunks.
+    ///
+    /// The size of the database grows as needed, each batch is
+    /// written in its own transaction.
+    ///
     /// This returns a [Searcher] object and the number of indexed documents.
-    pub fn index<S, D, I, P>(
-        &self,
-        docs: I,
-        path: P,
-        db_size: usize,
-    ) -> Result<(Searcher<D>, u32), DbError>
+    pub fn index<S, D, I, P>(&self, docs: I, path: P) -> Result<(Searcher<D>, u32), DbError>
     where
         S: AsRef<str>,
         I: IntoIterator<Item = (S, D)>,
@@ -365,63 +903,77 @@ impl Indexer {
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        let db = DB::truncate(path, db_size)?;
-        let mut rwtxn = db.env.write_txn()?;
+        let db = DB::truncate(path)?;
+        let aliases = self.aliases.normalized(&self.analyzer);
+        let phrases = Phrases::new(&self.phrases, &self.analyzer);
 
-        let mut batch = Batch::new();
+        let mut batch = Batch::new(self.window_len);
 
         let batch_size = self.batch_size.unwrap_or(u32::MAX);
-        let mut it = docs.into_iter();
-
-        let mut token_to_freq = GxHashMap::new();
+        // the common tokens are counted on all of the documents, so unless
+        // they are known beforehand the batches are merged after the last one
+        let mut counter = self.token_counter(Some(CORPUS_COUNTER_CAPACITY));
+        let counted = self.counts_common_tokens();
+        let known_common_tokens = (!counted).then(|| self.generate_common_tokens(&mut counter));
+        let mut chunks = Chunks::default();
         let mut next_doc_id = 0;
+        let mut num_docs = 0;
         let mut mmap_size = 0;
 
-        log::info!("Starting first batch");
-        // Index the first batch to generate the common tokens
-        let b = std::time::Instant::now();
-        for (content, doc) in it.by_ref() {
-            let doc_id = next_doc_id;
-            next_doc_id += 1;
-
-            batch.push(doc_id, content.as_ref(), doc, |token| {
-                let (_, freq) = token_to_freq
-                    .raw_entry_mut()
-                    .from_key(token)
-                    .or_insert_with(|| (token.to_owned().into_boxed_str(), 0));
-                *freq += 1;
-            });
-
-            if next_doc_id % batch_size == 0 {
-                break;
-            }
-        }
-        log::info!("First batch took {:?}", b.elapsed());
-
-        let common_tokens = self.generate_common_tokens(&token_to_freq);
-        drop(token_to_freq);
-
-        batch.flush(&db, &mut rwtxn, &common_tokens, &mut mmap_size)?;
-
-        // Index the rest of the documents
         log::info!("Starting new batch");
         let mut b = std::time::Instant::now();
-        for (content, doc) in it {
-            let doc_id = next_doc_id;
-            next_doc_id += 1;
-
-            batch.push(doc_id, content.as_ref(), doc, |_| {});
+        for (content, doc) in docs {
+            if counted {
+                counter.next_doc();
+            }
+            next_doc_id = batch.push(
+                next_doc_id,
+                content.as_ref(),
+                doc,
+                &aliases,
+                &self.analyzer,
+                &mut chunks,
+                |token| {
+                    if counted {
+                        counter.add(token);
+                    }
+                },
+            );
 
-            if next_doc_id % batch_size == 0 {
+            num_docs += 1;
+            if num_docs % batch_size == 0 {
                 log::info!("Batch took {:?}", b.elapsed());
                 b = std::time::Instant::now();
-                batch.flush(&db, &mut rwtxn, &common_tokens, &mut mmap_size)?;
+                let common_tokens = known_common_tokens.as_ref();
+                batch.flush(&db, common_tokens, &phrases, &mut mmap_size)?;
                 log::info!("Starting new batch");
             }
         }
 
         // Flush the last batch
-        batch.flush(&db, &mut rwtxn, &common_tokens, &mut mmap_size)?;
+        batch.flush(&db, known_common_tokens.as_ref(), &phrases, &mut mmap_size)?;
+
+        let common_tokens = match known_common_tokens {
+            Some(common_tokens) => common_tokens,
+            None => {
+                let common_tokens = self.generate_common_tokens(&mut counter);
+                log::info!("Merging the common tokens of {} batches", batch.batch_id);
+                let b = std::time::Instant::now();
+                for batch_id in 0..batch.batch_id {
+                    let tokenized = db.read_tokenized_batch(batch_id)?;
+                    batch.flush_tokenized(
+                        &db,
+                        tokenized,
+                        &common_tokens,
+                        &phrases,
+                        &mut mmap_size,
+                    )?;
+                }
+                log::info!("Merge took {:?}", b.elapsed());
+                common_tokens
+            }
+        };
+        drop(counter);
 
         let number_of_distinct_tokens = batch.estimate_number_of_distinct_tokens();
         log::debug!(
@@ -430,20 +982,390 @@ impl Indexer {
         );
 
         // Write to db
-        db.write_common_tokens(&mut rwtxn, &common_tokens)?;
-        db.generate_mmap_file(
-            number_of_distinct_tokens,
-            mmap_size,
-            batch.batch_id,
-            &mut rwtxn,
-        )?;
-
-        let b = std::time::Instant::now();
-        log::info!("Commiting");
-        rwtxn.commit()?;
-        log::info!("Commit took {:?}", b.elapsed());
+        let index_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        db.reserve_map_size_for_tokens(number_of_distinct_tokens)?;
+        db.write_txn(|rwtxn| {
+            db.write_index_id(rwtxn, index_id)?;
+            db.write_common_tokens(rwtxn, &common_tokens)?;
+            db.write_analyzer(rwtxn, &self.analyzer)?;
+            db.write_chunks(rwtxn, &chunks)?;
+            db.write_window_len(rwtxn, self.window_len)?;
+            db.write_phrases(rwtxn, &phrases)?;
+            db.generate_mmap_file(number_of_distinct_tokens, mmap_size, batch.batch_id, rwtxn)
+        })?;
+        db.remove_token_to_roaringish_packed_files(batch.batch_id)?;
 
+        // the environment can't be opened twice
+        drop(db);
         let searcher = Searcher::new(path)?;
-        Ok((searcher, next_doc_id))
+        Ok((searcher, num_docs))
+    }
+
+    /// Estimates the size of the index of a corpus with `total_docs`
+    /// documents, by indexing the `sample` in memory.
+    ///
+    /// The sample is tokenized and merged in batches the same way as
+    /// [Self::index], the documents are only serialized to measure them,
+    /// but the tokenized documents are kept in memory instead of on disk.
+    /// The sizes are extrapolated linearly with the number of documents,
+    /// except for the number of distinct tokens that follows Heaps' law.
+    pub fn estimate<S, D, I>(&self, sample: I, total_docs: u64) -> Result<IndexEstimate, DbError>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = (S, D)>,
+        D: Document,
+    {
+        /// Size of the header of each entry in LMDB.
+        const NODE_SIZE: u64 = 8;
+        /// Size of the offset of each Roaringish Packed in the token table.
+        const OFFSET_SIZE: u64 = 16;
+
+        let aliases = self.aliases.normalized(&self.analyzer);
+        let phrases = Phrases::new(&self.phrases, &self.analyzer);
+        let batch_size = self.batch_size.unwrap_or(u32::MAX);
+        let mut batch = Batch::<()>::new(self.window_len);
+        let mut chunks = Chunks::default();
+        let mut counter = self.token_counter(Some(CORPUS_COUNTER_CAPACITY));
+        let counted = self.counts_common_tokens();
+        let known_common_tokens = (!counted).then(|| self.generate_common_tokens(&mut counter));
+        let mut tokenized_batches = Vec::new();
+        // distinct tokens before the merge, this can't fail
+        let mut unmerged_tokens: HyperLogLogPlus<Box<str>, gxhash::GxBuildHasher> =
+            HyperLogLogPlus::new(18, gxhash::GxBuildHasher::default()).unwrap();
+        let mut next_doc_id = 0;
+        let mut sample_docs = 0u64;
+        let mut documents_bytes = 0;
+        let mut roaringish_packed_bytes = 0;
+        let mut token_table_bytes = 0;
+        let mut batch_tokens = 0;
+        let mut measure = |batch: &Batch<()>| {
+            for (token, token_id) in batch.token_to_token_id.iter() {
+                let packed = &batch.token_id_to_roaringish_packed[*token_id as usize];
+                roaringish_packed_bytes += packed.size_bytes() as u64;
+                token_table_bytes += token.len() as u64 + OFFSET_SIZE + NODE_SIZE;
+            }
+            batch_tokens += batch.token_to_token_id.len();
+        };
+        // number of distinct tokens after each power of 2 documents
+        let mut checkpoints = Vec::new();
+
+        let mut it = sample.into_iter();
+        loop {
+            let mut batch_docs = 0;
+            for (content, doc) in it.by_ref() {
+                documents_bytes += rkyv::to_bytes::<rkyv::rancor::Error>(&doc)?.len() as u64;
+                documents_bytes += std::mem::size_of::<u32>() as u64 + NODE_SIZE;
+
+                if counted {
+                    counter.next_doc();
+                }
+                next_doc_id = batch.push(
+                    next_doc_id,
+                    content.as_ref(),
+                    (),
+                    &aliases,
+                    &self.analyzer,
+                    &mut chunks,
+                    |token| {
+                        unmerged_tokens.insert(token);
+                        if counted {
+                            counter.add(token);
+                        }
+                    },
+                );
+
+                sample_docs += 1;
+                if sample_docs.is_power_of_two() {
+                    checkpoints.push((sample_docs, unmerged_tokens.count() as u64));
+                }
+                batch_docs += 1;
+                if batch_docs == batch_size {
+                    break;
+                }
+            }
+            if batch_docs == 0 {
+                break;
+            }
+
+            if let Some(common_tokens) = &known_common_tokens {
+                batch.merge_common_tokens(common_tokens, &phrases);
+            }
+            measure(&batch);
+            if known_common_tokens.is_none() {
+                tokenized_batches.push(batch.take_tokenized());
+            }
+            batch.clear();
+        }
+
+        let common_tokens = match known_common_tokens {
+            Some(common_tokens) => common_tokens,
+            None => {
+                let common_tokens = self.generate_common_tokens(&mut counter);
+                for tokenized in tokenized_batches {
+                    batch.merge_tokenized(tokenized, &common_tokens, &phrases);
+                    measure(&batch);
+                }
+                common_tokens
+            }
+        };
+        drop(counter);
+
+        let unmerged_distinct_tokens = unmerged_tokens.count() as u64;
+        let distinct_tokens = batch.estimate_number_of_distinct_tokens();
+
+        // fits `distinct = k * docs ^ heaps_exponent` to the last checkpoint
+        // that has at most half of the documents, before the merge
+        let heaps_exponent = checkpoints
+            .iter()
+            .rev()
+            .find(|(docs, distinct)| *docs * 2 <= sample_docs && *distinct > 0)
+            .map(|(docs, distinct)| {
+                let docs_ratio = sample_docs as f64 / *docs as f64;
+                let distinct_ratio = unmerged_distinct_tokens as f64 / *distinct as f64;
+                (distinct_ratio.ln() / docs_ratio.ln()).clamp(0.0, 1.0)
+            })
+            .unwrap_or(1.0);
+
+        let scale = match sample_docs {
+            0 => 0.0,
+            _ => total_docs as f64 / sample_docs as f64,
+        };
+        let total_distinct_tokens = distinct_tokens as f64 * scale.powf(heaps_exponent);
+        let avg_token_bytes = match batch_tokens {
+            0 => 0.0,
+            n => token_table_bytes as f64 / n as f64,
+        };
+
+        Ok(IndexEstimate {
+            sample_docs,
+            total_docs,
+            num_common_tokens: common_tokens.len(),
+            distinct_tokens: total_distinct_tokens as u64,
+            heaps_exponent,
+            // each Roaringish Packed is aligned to 64 bytes
+            roaringish_packed_bytes: (roaringish_packed_bytes as f64 * scale
+                + total_distinct_tokens * 64.0) as u64,
+            documents_bytes: (documents_bytes as f64 * scale) as u64,
+            token_table_bytes: (avg_token_bytes * total_distinct_tokens) as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CHUNK_STEP, Indexer, MAX_VALUE};
+    use crate::{
+        Aliases, Analyzer, CommonTokens, DocSet, NaiveIntersect, Searcher,
+        test_utils::{Rng, TempDir},
+    };
+
+    fn search(searcher: &Searcher<u32>, q: &str) -> Vec<u32> {
+        searcher.search::<NaiveIntersect>(q).0.unwrap_or_default()
+    }
+
+    #[test]
+    fn long_documents_are_split_into_chunks() {
+        let mut words: Vec<String> = (0..CHUNK_STEP + MAX_VALUE / 4)
+            .map(|i| format!("w{}", i % 512))
+            .collect();
+        // crosses the begining of the second chunk
+        let b = CHUNK_STEP as usize - 1;
+        for (i, word) in ["alpha", "beta", "gamma"].into_iter().enumerate() {
+            words[b + i] = word.to_string();
+        }
+        // crosses the end of the first chunk, inside of the overlap
+        let b = MAX_VALUE as usize - 2;
+        for (i, word) in ["delta", "epsilon", "zeta", "eta"].into_iter().enumerate() {
+            words[b + i] = word.to_string();
+        }
+        let docs = vec![
+            ("alpha".to_string(), 0),
+            (words.join(" "), 1),
+            ("alpha beta delta epsilon".to_string(), 2),
+        ];
+
+        let dir = TempDir::new("chunks");
+        let (searcher, n) = Indexer::new(None, None).index(docs, dir.path()).unwrap();
+        assert_eq!(n, 3);
+
+        let documents = |q| {
+            let r = searcher.search::<NaiveIntersect>(q);
+            r.get_documents().unwrap()
+        };
+        assert_eq!(documents("alpha beta gamma"), [1]);
+        assert_eq!(documents("delta epsilon zeta eta"), [1]);
+        assert_eq!(documents("alpha beta"), [1, 2]);
+        assert_eq!(documents("delta epsilon"), [1, 2]);
+        assert_eq!(documents("alpha"), [0, 1, 2]);
+
+        // the chunks of the documents are also searched
+        let long = DocSet::from(vec![1]);
+        let r = searcher.search_within::<NaiveIntersect>(&long, "delta epsilon zeta");
+        assert_eq!(r.get_documents().unwrap(), [1]);
+        let ids = searcher.search::<NaiveIntersect>("alpha beta delta");
+        let last = DocSet::from(ids.get_internal_document_ids().unwrap().to_vec());
+        let r = searcher.search_within::<NaiveIntersect>(&last, "alpha beta");
+        assert_eq!(r.get_documents().unwrap(), [2]);
+    }
+
+    #[test]
+    fn estimate_matches_the_index() {
+        // skewed frequencies, so there are common tokens
+        let mut rng = Rng(7);
+        let docs: Vec<(String, u32)> = (0..600)
+            .map(|i| {
+                let words: Vec<String> = (0..40)
+                    .map(|_| {
+                        let w = rng.below(2000);
+                        format!("w{}", w * rng.below(2000) / 2000)
+                    })
+                    .collect();
+                (words.join(" "), i)
+            })
+            .collect();
+
+        let indexer = Indexer::new(Some(100), Some(CommonTokens::FixedNum(20)));
+        let full = indexer.estimate(docs.iter().cloned(), 600).unwrap();
+        let half = indexer.estimate(docs[..300].iter().cloned(), 600).unwrap();
+        assert_eq!(full.sample_docs, 600);
+        assert_eq!(full.num_common_tokens, 20);
+
+        let dir = TempDir::new("estimate");
+        indexer.index(docs, dir.path()).unwrap();
+        let packed = std::fs::metadata(dir.path().join("roaringish_packed")).unwrap();
+        // the whole corpus is split in the same batches as the index, only
+        // the distinct tokens counted by the HyperLogLog can be different
+        let ratio = |estimate: u64| estimate as f64 / packed.len() as f64;
+        assert!((0.99..1.01).contains(&ratio(full.roaringish_packed_bytes)));
+        assert!((0.8..1.25).contains(&ratio(half.roaringish_packed_bytes)));
+    }
+
+    #[test]
+    fn common_tokens_of_all_batches() {
+        // the first batch isn't representative
+        let docs = || {
+            (0..200).map(|i| match i < 50 {
+                true => (format!("alpha beta x{i}"), i),
+                false => (format!("gamma delta gamma delta y{i}"), i),
+            })
+        };
+        let contents = || docs().map(|(content, _)| content);
+
+        let counted = Indexer::new(Some(50), Some(CommonTokens::FixedNum(2)));
+        let known = Indexer::new(Some(50), Some(CommonTokens::FixedNum(2)))
+            .with_corpus_common_tokens(contents());
+        let min_doc_frequency = Indexer::new(Some(50), Some(CommonTokens::MinDocFrequency(0.5)));
+        for indexer in [counted, known, min_doc_frequency] {
+            let dir = TempDir::new("all_batches");
+            let indexer = indexer.with_phrases(["beta x7"]);
+            let (searcher, _) = indexer.index(docs(), dir.path()).unwrap();
+
+            let explain = searcher.explain::<NaiveIntersect>("gamma delta").unwrap();
+            assert_eq!(explain.final_tokens.len(), 1);
+            assert_eq!(explain.num_documents, 150);
+            let explain = searcher.explain::<NaiveIntersect>("beta x7").unwrap();
+            assert_eq!(explain.final_tokens.len(), 1);
+            assert_eq!(search(&searcher, "delta gamma delta y60"), [60]);
+            assert_eq!(search(&searcher, "alpha beta x3"), [3]);
+
+            // the tokenized batches were removed
+            for entry in std::fs::read_dir(dir.path()).unwrap() {
+                let name = entry.unwrap().file_name();
+                assert!(!name.to_string_lossy().starts_with("temp_"));
+            }
+        }
+    }
+
+    #[test]
+    fn phrases_are_merged() {
+        let long = "the lord of the rings the return of the king";
+        let docs = vec![
+            (format!("i love {long} and new york"), 0),
+            ("new jersey is close to york".to_string(), 1),
+            (format!("the king of the rings, {long}"), 2),
+        ];
+
+        let dir = TempDir::new("phrases");
+        let indexer = Indexer::new(None, None).with_phrases(["New York", long, "york"]);
+        let (searcher, _) = indexer.index(docs, dir.path()).unwrap();
+
+        // the phrase is longer than the window
+        for (q, num_merged, expected) in [("new york", 2, vec![0]), (long, 10, vec![0, 2])] {
+            let explain = searcher.explain::<NaiveIntersect>(q).unwrap();
+            let final_tokens = explain.final_tokens.iter();
+            let merged: Vec<_> = final_tokens.map(|token| token.num_merged).collect();
+            assert_eq!(merged, [num_merged]);
+            assert_eq!(search(&searcher, q), expected);
+        }
+        // inside of a longer query
+        assert_eq!(search(&searcher, "love new york"), Vec::<u32>::new());
+        assert_eq!(search(&searcher, "and new york"), [0]);
+        assert_eq!(search(&searcher, &format!("love {long} and")), [0]);
+        assert_eq!(search(&searcher, "york"), [0, 1]);
+    }
+
+    #[test]
+    fn aliases_are_searchable() {
+        let mut aliases = Aliases::new();
+        assert!(aliases.add("NYC", "New York City"));
+        assert!(!aliases.add("new york", "nyc"));
+        let docs = vec![("i love nyc so much", 0), ("new york is big", 1)];
+
+        let dir = TempDir::new("aliases");
+        let indexer = Indexer::new(None, Some(CommonTokens::List(["so".into()].into())))
+            .with_aliases(aliases);
+        let (searcher, _) = indexer.index(docs, dir.path()).unwrap();
+
+        assert_eq!(search(&searcher, "nyc so"), [0]);
+        // the alias overlaps with the tokens that follow it
+        assert_eq!(search(&searcher, "love new york city"), [0]);
+        assert_eq!(search(&searcher, "new york city so"), Vec::<u32>::new());
+        assert_eq!(search(&searcher, "new york"), [0, 1]);
+        assert_eq!(search(&searcher, "love york"), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn aliases_of_identifiers() {
+        let mut aliases = Aliases::new();
+        aliases.add("getUser", "fetchAccount");
+        let docs = vec![("call getUser now", 0), ("get user", 1)];
+
+        let dir = TempDir::new("aliases_code");
+        let indexer = Indexer::new(None, None)
+            .with_analyzer(Analyzer::new().with_source_code(true))
+            .with_aliases(aliases);
+        let (searcher, _) = indexer.index(docs, dir.path()).unwrap();
+
+        // the alias is split like the identifiers of the documents
+        assert_eq!(search(&searcher, "call fetchAccount now"), [0]);
+        assert_eq!(search(&searcher, "fetch account now"), [0]);
+        assert_eq!(search(&searcher, "getUser"), [0]);
+        assert_eq!(search(&searcher, "get user"), [0, 1]);
+    }
+
+    #[cfg(feature = "stemming")]
+    #[test]
+    fn aliases_are_stemmed() {
+        use crate::{Language, SearchOptions, StemMode};
+
+        let mut aliases = Aliases::new();
+        aliases.add("car", "running vehicles");
+        let docs = vec![("my car", 0), ("my running vehicles", 1)];
+
+        let dir = TempDir::new("aliases_stemmed");
+        let analyzer = Analyzer::new().with_stemmer(Language::English, StemMode::Stack);
+        let indexer = Indexer::new(None, None)
+            .with_analyzer(analyzer)
+            .with_aliases(aliases);
+        let (searcher, _) = indexer.index(docs, dir.path()).unwrap();
+
+        assert_eq!(search(&searcher, "my run vehicle"), [0, 1]);
+        assert_eq!(search(&searcher, "my runs vehicles"), [0, 1]);
+        let exact = SearchOptions::new().with_exact(true);
+        let r = searcher.search_with_options::<NaiveIntersect>("my running vehicles", &exact);
+        assert_eq!(r.0.unwrap(), [0, 1]);
     }
 }
@@ -23,6 +23,10 @@
 //!
 //! It's highly recommended to compile this crate with `-C llvm-args=-align-all-functions=6`.
 //!
+//! ## Features
+//!
+//! * `stemming`: Porter2 English stemmer, used by `Analyzer::with_stemmer`.
+//!
 //! ## Usage
 //!
 //! ```rust
@@ -40,11 +44,10 @@
 //!     ("look at my beautiful hamster", 35),
 //! ];
 //! let index_name = "./index";
-//! let db_size = 1024 * 1024;
 //!
 //! // Indexes the documents returned by the iterator `it`.
-//! // The index will be created at `index_name` with the given `db_size`.
-//! let (searcher, num_indexed_documents) = indexer.index(docs, index_name, db_size)?;
+//! // The index will be created at `index_name`, it grows as needed.
+//! let (searcher, num_indexed_documents) = indexer.index(docs, index_name)?;
 //!
 //! // Search by the string "78"
 //! let result = searcher.search::<SimdIntersect>("at my beautiful")?;
@@ -52,15 +55,30 @@
 //! let documents = result.get_documents()?;
 //! ```
 
+mod aliases;
 mod allocator;
+mod analyzer;
+mod cache;
+mod chunks;
 mod codecs;
 mod db;
 mod decreasing_window_iter;
+mod doc_set;
+mod documents;
 mod error;
+mod estimate;
+mod explain;
 mod indexer;
+mod options;
+mod phrases;
+mod query_log;
 mod roaringish;
 mod searcher;
 mod stats;
+mod synonyms;
+#[cfg(test)]
+mod test_utils;
+mod token_counter;
 mod utils;
 
 use allocator::Aligned64;
@@ -69,15 +87,25 @@ use roaringish::BorrowRoaringishPacked;
 use roaringish::RoaringishPacked;
 use utils::{normalize, tokenize};
 
+pub use aliases::Aliases;
+pub use analyzer::{Analyzer, Cjk, Language, Normalization, Punctuation, StemMode};
+pub use cache::SearchCache;
 pub use db::Document;
+pub use doc_set::DocSet;
+pub use documents::{ArchivedDocuments, ArchivedDocumentsIter, Snapshot};
 pub use error::{DbError, GetDocumentError, SearchError};
+pub use estimate::IndexEstimate;
+pub use explain::{Explain, ExplainStep, ExplainToken};
 pub use indexer::CommonTokens;
 pub use indexer::Indexer;
+pub use options::{CancellationToken, SearchOptions};
+pub use query_log::{QueryCost, QueryLogReport};
 pub use stats::Stats;
+pub use synonyms::SynonymMap;
 
 pub use roaringish::intersect::naive::NaiveIntersect;
 
-pub use roaringish::intersect::Intersection;
 #[cfg(target_feature = "avx512f")]
 pub use roaringish::intersect::simd::SimdIntersect;
+pub use roaringish::intersect::{Intersection, IntersectionAlgorithm};
 pub use searcher::{SearchResult, Searcher};
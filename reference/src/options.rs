@@ -0,0 +1,213 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering::Relaxed},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::SearchError;
+
+/// Token used to cancel searches from another thread.
+///
+/// Cloning the token shares the same cancellation state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels all of the searches using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Relaxed);
+    }
+
+    /// Returns `true` if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Relaxed)
+    }
+}
+
+/// Limits applied to a single search.
+///
+/// By default there are no limits.
+///
+/// The deadline and the cancellation are checked between the steps
+/// of a search, e.g. before each intersection, but not inside of them.
+/// So a search can finish up to one intersection after its deadline
+/// or after being cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    max_tokens: Option<usize>,
+    max_intermediate_len: Option<usize>,
+    exact: bool,
+}
+
+impl SearchOptions {
+    /// Creates a new set of options without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the search with [SearchError::DeadlineExceeded]
+    /// if it's still running at `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Same as [Self::with_deadline], but the deadline is `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Stops the search with [SearchError::Cancelled] when `token` is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Fails the search with [SearchError::TooManyTokens] if the
+    /// query has more than `max_tokens` after tokenization.
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Fails the search with [SearchError::IntermediateTooBig] if an
+    /// intersection step could allocate buffers with more than
+    /// `max_intermediate_len` elements.
+    ///
+    /// This is checked before each step of the intersection. The result of a
+    /// step is bounded by its smallest side, but the buffers are also sized by
+    /// the left side, so this limits the size of the Roaringish Packed of the
+    /// tokens intersected with an intermediate result as well.
+    pub fn with_max_intermediate_len(mut self, max_intermediate_len: usize) -> Self {
+        self.max_intermediate_len = Some(max_intermediate_len);
+        self
+    }
+
+    /// Searches the tokens as they are written, without stemming.
+    ///
+    /// Only has an effect on indexes that use [crate::StemMode::Stack].
+    pub fn with_exact(mut self, exact: bool) -> Self {
+        self.exact = exact;
+        self
+    }
+
+    /// Returns `true` if the tokens are searched without stemming.
+    pub(crate) fn exact(&self) -> bool {
+        self.exact
+    }
+
+    /// Deadline of the search, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Checks if the search was cancelled or the deadline has passed.
+    ///
+    /// `partial` is only called if the deadline has passed.
+    pub(crate) fn check_interrupted(
+        &self,
+        partial: impl FnOnce() -> Option<Vec<u32>>,
+    ) -> Result<(), SearchError> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Err(SearchError::Cancelled);
+        }
+
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(SearchError::DeadlineExceeded(partial()));
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the query has too many tokens.
+    pub(crate) fn check_num_tokens(&self, num_tokens: usize) -> Result<(), SearchError> {
+        match self.max_tokens {
+            Some(max) if num_tokens > max => Err(SearchError::TooManyTokens(num_tokens, max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if an intermediate result with (at most)
+    /// `len` elements is too big.
+    pub(crate) fn check_intermediate_len(&self, len: usize) -> Result<(), SearchError> {
+        match self.max_intermediate_len {
+            Some(max) if len > max => Err(SearchError::IntermediateTooBig(len, max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{CancellationToken, SearchOptions};
+    use crate::{DocSet, Indexer, NaiveIntersect, SearchError, test_utils::TempDir};
+
+    #[test]
+    fn limits_are_returned() {
+        let dir = TempDir::new("options_limits");
+        let docs = vec![("look at my cat", 0), ("look at my dog", 1)];
+        let (searcher, _) = Indexer::new(None, None).index(docs, dir.path()).unwrap();
+        let search = |q, options: &SearchOptions| {
+            searcher.search_with_options::<NaiveIntersect>(q, options).0
+        };
+
+        let token = CancellationToken::new();
+        let options = SearchOptions::new().with_cancellation(token.clone());
+        assert_eq!(search("look at my", &options).unwrap(), [0, 1]);
+        token.cancel();
+        assert!(matches!(
+            search("look at my", &options),
+            Err(SearchError::Cancelled)
+        ));
+
+        let options = SearchOptions::new().with_max_tokens(2);
+        assert_eq!(search("my cat", &options).unwrap(), [0]);
+        assert!(matches!(
+            search("look at my", &options),
+            Err(SearchError::TooManyTokens(3, 2))
+        ));
+
+        let options = SearchOptions::new().with_max_intermediate_len(1);
+        assert!(matches!(
+            search("look at my", &options),
+            Err(SearchError::IntermediateTooBig(_, 1))
+        ));
+        assert!(matches!(
+            search("look", &options),
+            Err(SearchError::IntermediateTooBig(_, 1))
+        ));
+        let within = DocSet::from(vec![0, 1]);
+        for q in ["look", "look at my"] {
+            let r = searcher.search_within_with_options::<NaiveIntersect>(&within, q, &options);
+            assert!(matches!(r.0, Err(SearchError::IntermediateTooBig(_, 1))));
+        }
+        let within = DocSet::from(vec![1]);
+        let options = SearchOptions::new().with_max_intermediate_len(2);
+        let r = searcher.search_within_with_options::<NaiveIntersect>(&within, "look", &options);
+        assert_eq!(r.0.unwrap(), [1]);
+
+        let options = SearchOptions::new().with_deadline(Instant::now() - Duration::from_secs(1));
+        assert!(matches!(
+            search("look at my", &options),
+            Err(SearchError::DeadlineExceeded(None))
+        ));
+    }
+}
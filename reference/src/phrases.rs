@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use crate::Analyzer;
+
+/// Phrases indexed as a single merged token, like the merged common tokens,
+/// so searching them is a single lookup regardless of the frequency of
+/// their tokens.
+///
+/// Used by [crate::Indexer::with_phrases].
+#[derive(Debug, Default)]
+pub struct Phrases {
+    /// Tokens of each phrase separated by a space, the same
+    /// way as the merged common tokens.
+    phrases: HashSet<Box<str>>,
+    /// Tokens of each phrase.
+    tokens: Vec<Box<[Box<str>]>>,
+    /// Maximum number of tokens of the phrases.
+    max_len: usize,
+}
+
+impl From<HashSet<Box<str>>> for Phrases {
+    fn from(phrases: HashSet<Box<str>>) -> Self {
+        let mut me = Self::default();
+        for phrase in phrases.iter() {
+            me.insert(phrase.split(' ').map(|t| t.into()).collect());
+        }
+        me
+    }
+}
+
+impl Phrases {
+    /// Normalizes and tokenizes the `phrases` the same way as the
+    /// documents, phrases with a single token are ignored with a warning.
+    pub fn new<S: AsRef<str>>(phrases: &[S], analyzer: &Analyzer) -> Self {
+        let mut me = Self::default();
+        for phrase in phrases {
+            let phrase = analyzer.normalize(phrase.as_ref());
+            let tokens: Box<[Box<str>]> = analyzer
+                .tokenize(&phrase, false)
+                .map(|token| analyzer.index_token(&token.token).0.into())
+                .collect();
+            if tokens.len() < 2 {
+                log::warn!("Ignoring the phrase {phrase:?}, it doesn't have multiple tokens");
+                continue;
+            }
+            me.insert(tokens);
+        }
+        me
+    }
+
+    fn insert(&mut self, tokens: Box<[Box<str>]>) {
+        if tokens.len() < 2 || !self.phrases.insert(tokens.join(" ").into()) {
+            return;
+        }
+
+        self.max_len = self.max_len.max(tokens.len());
+        self.tokens.push(tokens);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.phrases.is_empty()
+    }
+
+    /// Returns `true` if `tokens`, separated by a space, is a phrase.
+    pub fn contains(&self, tokens: &str) -> bool {
+        self.phrases.contains(tokens)
+    }
+
+    /// Maximum number of tokens of the phrases.
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// Tokens of each phrase separated by a space.
+    pub fn phrases(&self) -> &HashSet<Box<str>> {
+        &self.phrases
+    }
+
+    /// Tokens of each phrase.
+    pub fn iter(&self) -> impl Iterator<Item = &[Box<str>]> {
+        self.tokens.iter().map(|tokens| tokens.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Phrases;
+    use crate::Analyzer;
+
+    #[test]
+    fn single_tokens_are_ignored() {
+        let phrases = [
+            "New  York",
+            "york",
+            "",
+            " ",
+            "new york",
+            "Lord of the Rings",
+        ];
+        let phrases = Phrases::new(&phrases, &Analyzer::default());
+        let mut merged: Vec<_> = phrases.phrases().iter().map(|p| p.as_ref()).collect();
+        merged.sort_unstable();
+        assert_eq!(merged, ["lord of the rings", "new york"]);
+        assert_eq!(phrases.max_len(), 4);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{cmp::Reverse, collections::HashSet, num::NonZero};
+
+use gxhash::{HashMap as GxHashMap, HashMapExt};
+
+use crate::{Analyzer, token_counter::TokenCounter};
+
+/// Estimated cost of a query of the log.
+#[derive(Clone, Debug)]
+pub struct QueryCost {
+    /// The query, as it first appears in the log.
+    pub query: String,
+    /// Number of times the query appears in the log.
+    pub count: u64,
+    /// Estimated cost without merging the common tokens.
+    pub cost_without_merge: u64,
+    /// Estimated cost with the common tokens.
+    pub cost: u64,
+}
+
+impl QueryCost {
+    /// Estimated cost saved by each search of the query.
+    pub fn gain(&self) -> u64 {
+        self.cost_without_merge - self.cost
+    }
+}
+
+/// Predicted gains of the common tokens on a query log.
+///
+/// The cost of a query is the sum of the lengths of the Roaringish Packed
+/// chosen by the merge and minimize phase, estimated with the frequency of
+/// the tokens in the corpus.
+///
+/// Returned by [crate::Indexer::replay_query_log].
+#[derive(Clone, Debug)]
+pub struct QueryLogReport {
+    /// Common tokens generated from the corpus, sorted.
+    pub common_tokens: Vec<String>,
+    /// Distinct queries of the log, sorted by decreasing total gain.
+    pub queries: Vec<QueryCost>,
+}
+
+impl QueryLogReport {
+    /// Estimated cost of replaying the log without merging the common tokens.
+    pub fn total_cost_without_merge(&self) -> u64 {
+        self.queries
+            .iter()
+            .map(|q| q.cost_without_merge * q.count)
+            .sum()
+    }
+
+    /// Estimated cost of replaying the log with the common tokens.
+    pub fn total_cost(&self) -> u64 {
+        self.queries.iter().map(|q| q.cost * q.count).sum()
+    }
+}
+
+/// Query of the log, after tokenization.
+struct Query {
+    query: String,
+    tokens: Vec<Box<str>>,
+    count: u64,
+    /// If the query can use the merged common tokens.
+    merge: bool,
+}
+
+/// Queries used to choose the common tokens that most reduce
+/// the estimated cost of searching them.
+pub struct QueryLog {
+    queries: Vec<Query>,
+    /// Maximum number of tokens merged into a single token.
+    window_len: NonZero<usize>,
+}
+
+impl QueryLog {
+    /// Tokenizes the `queries` the same way as a search,
+    /// the queries with the same tokens are counted together.
+    pub fn new<S: AsRef<str>>(
+        queries: &[S],
+        analyzer: &Analyzer,
+        window_len: NonZero<usize>,
+    ) -> Self {
+        let mut tokens_to_id: GxHashMap<Vec<Box<str>>, usize> = GxHashMap::new();
+        let mut log: Vec<Query> = Vec::new();
+        for query in queries {
+            let q = analyzer.normalize(query.as_ref());
+            let mut merge = analyzer.uses_common_tokens(false);
+            let tokens: Vec<Box<str>> = analyzer
+                .tokenize(&q, true)
+                .map(|token| {
+                    merge &= token.mergeable;
+                    analyzer.query_token(&token.token, false).into()
+                })
+                .collect();
+            if tokens.is_empty() {
+                continue;
+            }
+
+            match tokens_to_id.get(&tokens) {
+                Some(id) => log[*id].count += 1,
+                None => {
+                    tokens_to_id.insert(tokens.clone(), log.len());
+                    log.push(Query {
+                        query: query.as_ref().to_string(),
+                        tokens,
+                        count: 1,
+                        merge,
+                    });
+                }
+            }
+        }
+
+        Self {
+            queries: log,
+            window_len,
+        }
+    }
+
+    /// Makes the `counter` count every merged token that the queries can use.
+    pub fn track(&self, counter: &mut TokenCounter) {
+        for query in self.queries.iter().filter(|q| q.merge) {
+            for window in 2..=self.window_len.get() {
+                for tokens in query.tokens.windows(window) {
+                    counter.track(&tokens.join(" "));
+                }
+            }
+        }
+    }
+
+    /// Estimated cost of searching `query` with the `common_tokens`.
+    ///
+    /// This finds the cheapest way to merge the tokens, with the same rules
+    /// and scoring as [crate::db::DB::merge_and_minimize_tokens], but using
+    /// the frequency of the tokens as the length of their Roaringish Packed.
+    fn cost(
+        &self,
+        query: &Query,
+        common_tokens: &HashSet<Box<str>>,
+        counter: &TokenCounter,
+    ) -> u64 {
+        let window_len = self.window_len.get();
+        let tokens = &query.tokens;
+        if !query.merge || common_tokens.is_empty() {
+            return tokens.iter().map(|token| counter.freq(token)).sum();
+        }
+
+        // cost of the tokens starting at each position
+        let mut costs = vec![0; tokens.len() + 1];
+        for i in (0..tokens.len()).rev() {
+            let following = tokens[i + 1..]
+                .iter()
+                .take(window_len - 1)
+                .take_while(|t| common_tokens.contains(*t))
+                .count();
+            let is_first_common = common_tokens.contains(&tokens[i]) as usize;
+            let max_len = (following + 1 + is_first_common)
+                .min(window_len)
+                .min(tokens.len() - i);
+
+            costs[i] = (1..=max_len)
+                .map(|len| counter.freq(&tokens[i..i + len].join(" ")) + costs[i + len])
+                .min()
+                .unwrap_or(0);
+        }
+        costs[0]
+    }
+
+    /// Greedily chooses up to `max` common tokens, each time the one
+    /// that most reduces the estimated cost of the whole log.
+    pub fn select(&self, max: usize, counter: &TokenCounter) -> HashSet<Box<str>> {
+        let mut common_tokens = HashSet::new();
+        let mut costs: Vec<_> = self
+            .queries
+            .iter()
+            .map(|q| self.cost(q, &common_tokens, counter))
+            .collect();
+
+        // only the tokens of the queries can reduce their cost
+        let mut token_to_queries: GxHashMap<&str, Vec<usize>> = GxHashMap::new();
+        for (id, query) in self.queries.iter().enumerate().filter(|(_, q)| q.merge) {
+            for token in query.tokens.iter() {
+                let ids = token_to_queries.entry(token.as_ref()).or_default();
+                if ids.last() != Some(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+
+        while common_tokens.len() < max {
+            let mut best: Option<(u64, Reverse<&str>)> = None;
+            for (token, ids) in token_to_queries.iter() {
+                common_tokens.insert(Box::from(*token));
+                let gain: u64 = ids
+                    .iter()
+                    .map(|id| {
+                        let query = &self.queries[*id];
+                        let cost = self.cost(query, &common_tokens, counter);
+                        costs[*id].saturating_sub(cost) * query.count
+                    })
+                    .sum();
+                common_tokens.remove(*token);
+
+                // ties are broken by the token, so the choice is deterministic
+                let candidate = (gain, Reverse(*token));
+                if gain > 0 && best.is_none_or(|best| candidate > best) {
+                    best = Some(candidate);
+                }
+            }
+
+            let Some((gain, Reverse(token))) = best else {
+                break;
+            };
+            log::debug!("Common token {token:?} reduces the cost by {gain}");
+            common_tokens.insert(Box::from(token));
+            // This can't fail, the token was just found in the map
+            for id in token_to_queries.remove(token).unwrap() {
+                costs[id] = self.cost(&self.queries[id], &common_tokens, counter);
+            }
+        }
+
+        common_tokens
+    }
+
+    /// Estimates the cost of each query with and without the `common_tokens`.
+    pub fn report(
+        &self,
+        common_tokens: &HashSet<Box<str>>,
+        counter: &TokenCounter,
+    ) -> QueryLogReport {
+        let mut queries: Vec<_> = self
+            .queries
+            .iter()
+            .map(|q| QueryCost {
+                query: q.query.clone(),
+                count: q.count,
+                cost_without_merge: self.cost(q, &HashSet::new(), counter),
+                cost: self.cost(q, common_tokens, counter),
+            })
+            .collect();
+        queries.sort_by_key(|q| Reverse(q.gain() * q.count));
+
+        let mut common_tokens: Vec<_> = common_tokens.iter().map(|t| t.to_string()).collect();
+        common_tokens.sort_unstable();
+
+        QueryLogReport {
+            common_tokens,
+            queries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::QueryLog;
+    use crate::{Analyzer, db::DEFAULT_WINDOW_LEN, token_counter::TokenCounter};
+
+    #[test]
+    fn selects_the_tokens_of_the_queries() {
+        let queries = ["a b", "A  b", "c r1", "a b", "a B", "a b"];
+        let log = QueryLog::new(&queries, &Analyzer::default(), DEFAULT_WINDOW_LEN);
+        assert_eq!(log.queries.len(), 2);
+        assert_eq!(
+            (log.queries[0].query.as_str(), log.queries[0].count),
+            ("a b", 5)
+        );
+
+        let mut counter = TokenCounter::new(None, DEFAULT_WINDOW_LEN);
+        log.track(&mut counter);
+        for i in 0..100 {
+            counter.next_doc();
+            for token in ["a", "b", "c", &format!("r{i}")] {
+                counter.add(token);
+            }
+        }
+
+        let set = |tokens: &[&str]| -> HashSet<Box<str>> {
+            tokens.iter().map(|token| Box::from(*token)).collect()
+        };
+        let query = &log.queries[0];
+        assert_eq!(log.cost(query, &set(&[]), &counter), 200);
+        assert_eq!(log.cost(query, &set(&["b"]), &counter), 100);
+        let query = &log.queries[1];
+        assert_eq!(log.cost(query, &set(&[]), &counter), 101);
+        assert_eq!(log.cost(query, &set(&["c"]), &counter), 1);
+
+        // "a" and "b" have the same gain, the ties are broken by the token
+        assert_eq!(log.select(1, &counter), set(&["a"]));
+        // "b" doesn't reduce the cost anymore
+        assert_eq!(log.select(3, &counter), set(&["a", "c"]));
+
+        let report = log.report(&log.select(3, &counter), &counter);
+        assert_eq!(report.common_tokens, ["a", "c"]);
+        assert_eq!(report.queries[0].query, "a b");
+        assert_eq!(report.total_cost_without_merge(), 5 * 200 + 101);
+        assert_eq!(report.total_cost(), 5 * 100 + 1);
+        assert!(report.total_cost() <= report.total_cost_without_merge());
+    }
+}
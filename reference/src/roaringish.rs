@@ -1,7 +1,9 @@
 pub mod intersect;
+mod union;
 
 use intersect::{
-    Intersect, gallop_first::GallopIntersectFirst, gallop_second::GallopIntersectSecond,
+    Intersect, IntersectionAlgorithm, gallop_first::GallopIntersectFirst,
+    gallop_second::GallopIntersectSecond,
 };
 use rkyv::{Archive, Serialize, with::InlineAsBox};
 use std::{
@@ -198,7 +200,7 @@ impl<'a, A> RoaringishPackedKind<'a, A> {
 ///
 /// The data structure should be ordered by the
 /// document ID and group.
-#[derive(PartialEq, Eq, Debug, Serialize, Archive)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Archive)]
 #[repr(transparent)]
 pub struct RoaringishPacked(Vec<u64, Aligned64>);
 
@@ -299,13 +301,66 @@ impl<'a> BorrowRoaringishPacked<'a, Aligned> {
         Self(packed, PhantomData)
     }
 
-    #[inline(never)]
+    /// Splits the document IDs in at most `n` ranges with roughly
+    /// the same number of elements.
+    ///
+    /// Returns the first document ID of each range, except
+    /// for the first range that always starts at 0.
+    pub fn partition_doc_ids(&self, n: usize) -> Vec<u32> {
+        if self.0.is_empty() {
+            return Vec::new();
+        }
+
+        let mut boundaries: Vec<u32> = (1..n)
+            .map(|i| unpack_doc_id(self.0[i * self.0.len() / n]))
+            .filter(|doc_id| *doc_id > 0)
+            .collect();
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Gets the elements of the documents in the range `begin..end`,
+    /// if `end` is [None] the range is unbounded.
+    ///
+    /// To keep the 64 byte alignment the begining is moved back
+    /// to the closest multiple of 8 elements, so it may also contain
+    /// some documents before `begin`.
+    pub fn doc_id_range(self, begin: u32, end: Option<u32>) -> Self {
+        if self.0.is_empty() {
+            return self;
+        }
+
+        let b = self.0.partition_point(|p| unpack_doc_id(*p) < begin);
+        let e = match end {
+            Some(end) => self.0.partition_point(|p| unpack_doc_id(*p) < end),
+            None => self.0.len(),
+        };
+        let aligned_b = b / 8 * 8;
+        Self::new_raw(&self.0[aligned_b..e])
+    }
+
+    #[inline(always)]
     pub fn intersect<I: Intersection>(
         self,
-        mut rhs: Self,
+        rhs: Self,
         lhs_len: u32,
         stats: &Stats,
     ) -> RoaringishPacked {
+        self.intersect_with_algorithms::<I>(rhs, lhs_len, stats).0
+    }
+
+    /// Same as [Self::intersect], but also returns the algorithms used in the
+    /// first and second phase of the intersection.
+    ///
+    /// The algorithms are [None] if one of the sides is empty, since no
+    /// intersection is performed.
+    #[inline(never)]
+    pub fn intersect_with_algorithms<I: Intersection>(
+        self,
+        mut rhs: Self,
+        lhs_len: u32,
+        stats: &Stats,
+    ) -> (RoaringishPacked, Option<[IntersectionAlgorithm; 2]>) {
         const FIRST_GALLOP_INTERSECT: usize = 650;
         const SECOND_GALLOP_INTERSECT: usize = 120;
 
@@ -350,7 +405,7 @@ impl<'a> BorrowRoaringishPacked<'a, Aligned> {
         let mut lhs = self;
 
         if lhs.0.is_empty() || rhs.0.is_empty() {
-            return RoaringishPacked::default();
+            return (RoaringishPacked::default(), None);
         }
 
         let b = std::time::Instant::now();
@@ -359,6 +414,12 @@ impl<'a> BorrowRoaringishPacked<'a, Aligned> {
             .first_binary_search
             .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
 
+        // skipping the begining can leave a side empty,
+        // e.g. with the ranges of a parallel search
+        if lhs.0.is_empty() || rhs.0.is_empty() {
+            return (RoaringishPacked::default(), None);
+        }
+
         let b = std::time::Instant::now();
         // this can't fail we just checked
         let proportion = lhs.len().max(rhs.len()) / lhs.len().min(rhs.len());
@@ -370,7 +431,11 @@ impl<'a> BorrowRoaringishPacked<'a, Aligned> {
                 .first_intersect
                 .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
 
-            return Self::merge_results(packed, msb_packed, stats);
+            let algorithms = [IntersectionAlgorithm::GallopFirst; 2];
+            return (
+                Self::merge_results(packed, msb_packed, stats),
+                Some(algorithms),
+            );
         }
         let (packed, msb_packed) = I::intersect::<true>(lhs, rhs, lhs_len, stats);
         stats
@@ -390,21 +455,34 @@ impl<'a> BorrowRoaringishPacked<'a, Aligned> {
             .len()
             .max(rhs.len())
             .checked_div(msb_packed.len().min(rhs.len()));
-        let (msb_packed, _) = match proportion {
+        let ((msb_packed, _), second_algorithm) = match proportion {
             Some(proportion) => {
                 if proportion >= SECOND_GALLOP_INTERSECT {
-                    GallopIntersectSecond::intersect::<false>(msb_packed, rhs, lhs_len, stats)
+                    (
+                        GallopIntersectSecond::intersect::<false>(msb_packed, rhs, lhs_len, stats),
+                        GallopIntersectSecond::ALGORITHM,
+                    )
                 } else {
-                    I::intersect::<false>(msb_packed, rhs, lhs_len, stats)
+                    (
+                        I::intersect::<false>(msb_packed, rhs, lhs_len, stats),
+                        I::ALGORITHM,
+                    )
                 }
             }
-            None => I::intersect::<false>(msb_packed, rhs, lhs_len, stats),
+            None => (
+                I::intersect::<false>(msb_packed, rhs, lhs_len, stats),
+                I::ALGORITHM,
+            ),
         };
         stats
             .second_intersect
             .fetch_add(b.elapsed().as_micros() as u64, Relaxed);
 
-        Self::merge_results(packed, msb_packed, stats)
+        let algorithms = [I::ALGORITHM, second_algorithm];
+        (
+            Self::merge_results(packed, msb_packed, stats),
+            Some(algorithms),
+        )
     }
 
     /// Merges the results of the first and second phase of the intersection.
@@ -511,6 +589,36 @@ impl<'a> BorrowRoaringishPacked<'a, Aligned> {
 }
 
 impl<A> BorrowRoaringishPacked<'_, A> {
+    /// Keeps only the elements of the documents in `doc_ids`,
+    /// that should be sorted and without duplicates.
+    pub fn filter_doc_ids(&self, doc_ids: &[u32]) -> RoaringishPacked {
+        let mut packed = RoaringishPacked::default();
+        let mut i = 0;
+        for doc_id in doc_ids.iter().copied() {
+            let rest = &self.0[i..];
+            // if there are few document IDs it's
+            // faster to binary search the begining
+            let begin = if doc_ids.len() < self.0.len() / 8 {
+                rest.partition_point(|p| unpack_doc_id(*p) < doc_id)
+            } else {
+                rest.iter()
+                    .take_while(|p| unpack_doc_id(**p) < doc_id)
+                    .count()
+            };
+            let len = rest[begin..]
+                .iter()
+                .take_while(|p| unpack_doc_id(**p) == doc_id)
+                .count();
+            packed.0.extend_from_slice(&rest[begin..begin + len]);
+
+            i += begin + len;
+            if i == self.0.len() {
+                break;
+            }
+        }
+        packed
+    }
+
     /// Gets the distinct document IDs from the Roaringish Packed.
     #[cfg(not(target_feature = "avx512f"))]
     #[inline(always)]
@@ -16,11 +16,27 @@ mod private {
 /// Allows a type to be used as an intersection algorithm when searching.
 pub trait Intersection: Intersect {}
 
+/// Identifies the algorithm used in one phase of an intersection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntersectionAlgorithm {
+    /// [simd::SimdIntersect].
+    Simd,
+    /// [naive::NaiveIntersect].
+    Naive,
+    /// [gallop_first::GallopIntersectFirst].
+    GallopFirst,
+    /// [gallop_second::GallopIntersectSecond].
+    GallopSecond,
+}
+
 /// Necessary functions for an intersection algorithm.
 ///
 /// The intersection is done in two phases that's why
 /// the function have a `FIRST` const generic.
 pub trait Intersect: private::IntersectSeal {
+    /// Algorithm implemented by this type.
+    const ALGORITHM: IntersectionAlgorithm;
+
     /// Responsible for allocating the result buffers
     /// and compute the necessary values before starting
     /// the intersection.
@@ -5,13 +5,15 @@ use crate::{
     roaringish::{ADD_ONE_GROUP, Aligned, clear_values, unpack_values},
 };
 
-use super::{Intersect, Intersection, private::IntersectSeal};
+use super::{Intersect, Intersection, IntersectionAlgorithm, private::IntersectSeal};
 
 pub struct GallopIntersectFirst;
 impl IntersectSeal for GallopIntersectFirst {}
 impl Intersection for GallopIntersectFirst {}
 
 impl Intersect for GallopIntersectFirst {
+    const ALGORITHM: IntersectionAlgorithm = IntersectionAlgorithm::GallopFirst;
+
     fn inner_intersect<const FIRST: bool>(
         lhs: BorrowRoaringishPacked<'_, Aligned>,
         rhs: BorrowRoaringishPacked<'_, Aligned>,
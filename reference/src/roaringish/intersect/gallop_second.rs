@@ -5,13 +5,15 @@ use crate::{
     roaringish::{Aligned, clear_values, unpack_values},
 };
 
-use super::{Intersect, Intersection, private::IntersectSeal};
+use super::{Intersect, Intersection, IntersectionAlgorithm, private::IntersectSeal};
 
 pub struct GallopIntersectSecond;
 impl IntersectSeal for GallopIntersectSecond {}
 impl Intersection for GallopIntersectSecond {}
 
 impl Intersect for GallopIntersectSecond {
+    const ALGORITHM: IntersectionAlgorithm = IntersectionAlgorithm::GallopSecond;
+
     fn inner_intersect<const FIRST: bool>(
         lhs: BorrowRoaringishPacked<'_, Aligned>,
         rhs: BorrowRoaringishPacked<'_, Aligned>,
@@ -6,7 +6,7 @@ use crate::{
     roaringish::{ADD_ONE_GROUP, Aligned, BorrowRoaringishPacked, clear_values, unpack_values},
 };
 
-use super::{Intersect, Intersection, private::IntersectSeal};
+use super::{Intersect, Intersection, IntersectionAlgorithm, private::IntersectSeal};
 
 /// Naive intersection algorithm.
 pub struct NaiveIntersect;
@@ -14,6 +14,8 @@ impl IntersectSeal for NaiveIntersect {}
 impl Intersection for NaiveIntersect {}
 
 impl Intersect for NaiveIntersect {
+    const ALGORITHM: IntersectionAlgorithm = IntersectionAlgorithm::Naive;
+
     #[inline(always)]
     fn inner_intersect<const FIRST: bool>(
         lhs: BorrowRoaringishPacked<'_, Aligned>,
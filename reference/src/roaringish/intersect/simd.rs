@@ -20,7 +20,7 @@ use crate::{
     },
 };
 
-use super::{Intersect, private::IntersectSeal};
+use super::{Intersect, IntersectionAlgorithm, private::IntersectSeal};
 use super::{Intersection, naive::NaiveIntersect};
 use crate::roaringish::Aligned;
 
@@ -119,6 +119,8 @@ impl IntersectSeal for SimdIntersect {}
 impl Intersection for SimdIntersect {}
 
 impl Intersect for SimdIntersect {
+    const ALGORITHM: IntersectionAlgorithm = IntersectionAlgorithm::Simd;
+
     #[inline(always)]
     fn inner_intersect<const FIRST: bool>(
         lhs: BorrowRoaringishPacked<'_, Aligned>,
@@ -0,0 +1,201 @@
+#[cfg(target_feature = "avx512f")]
+use std::simd::{Simd, cmp::SimdPartialOrd};
+
+use crate::allocator::Aligned64;
+
+#[cfg(target_feature = "avx512f")]
+use super::clear_values_simd;
+use super::{BorrowRoaringishPacked, RoaringishPacked, clear_values};
+
+impl<A> BorrowRoaringishPacked<'_, A> {
+    /// Union of multiple Roaringish Packed, keeping the positions.
+    ///
+    /// Elements with the same document ID and group are combined by
+    /// doing a bitwise OR of their values. The result can be used on
+    /// both sides of an intersection.
+    ///
+    /// The lists are merged in pairs, so each element
+    /// is only copied `log2(lists.len())` times.
+    pub fn union(lists: &[Self]) -> RoaringishPacked {
+        let mut merged: Vec<RoaringishPacked> = lists
+            .chunks(2)
+            .map(|lists| match lists {
+                [lhs, rhs] => merge(lhs.0, rhs.0),
+                [packed] => merge(packed.0, &[]),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        while merged.len() > 1 {
+            let mut next = Vec::with_capacity(merged.len().div_ceil(2));
+            let mut it = merged.into_iter();
+            while let Some(lhs) = it.next() {
+                match it.next() {
+                    Some(rhs) => next.push(merge(&lhs, &rhs)),
+                    None => next.push(lhs),
+                }
+            }
+            merged = next;
+        }
+
+        merged.pop().unwrap_or_default()
+    }
+}
+
+/// Merges two Roaringish Packed together.
+#[cfg(not(target_feature = "avx512f"))]
+#[inline(always)]
+fn merge(lhs: &[u64], rhs: &[u64]) -> RoaringishPacked {
+    let mut packed = Vec::with_capacity_in(lhs.len() + rhs.len(), Aligned64::default());
+    let mut i = 0;
+    let mut j = 0;
+    while i < lhs.len() && j < rhs.len() {
+        let l = lhs[i];
+        let r = rhs[j];
+        match clear_values(l).cmp(&clear_values(r)) {
+            std::cmp::Ordering::Less => {
+                packed.push(l);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                packed.push(r);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                packed.push(l | r);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    packed.extend_from_slice(&lhs[i..]);
+    packed.extend_from_slice(&rhs[j..]);
+    RoaringishPacked(packed)
+}
+
+/// Merges two Roaringish Packed together.
+///
+/// Compares a block of 8 elements with the head of the other
+/// side at once, so runs of elements that don't overlap
+/// with the other side are copied together.
+#[cfg(target_feature = "avx512f")]
+#[inline(always)]
+fn merge(lhs: &[u64], rhs: &[u64]) -> RoaringishPacked {
+    const N: usize = 8;
+
+    /// Number of elements at the begining of `packed` that
+    /// come before `head`, looking at most at `N` elements.
+    #[inline(always)]
+    fn count_before(packed: &[u64], head: u64) -> usize {
+        match packed.first_chunk::<N>() {
+            Some(block) => {
+                let block = clear_values_simd(Simd::from_array(*block));
+                let mask = block.simd_lt(Simd::splat(clear_values(head))).to_bitmask();
+                mask.trailing_ones() as usize
+            }
+            None => packed
+                .iter()
+                .take_while(|p| clear_values(**p) < clear_values(head))
+                .count(),
+        }
+    }
+
+    let mut packed = Vec::with_capacity_in(lhs.len() + rhs.len(), Aligned64::default());
+    let mut i = 0;
+    let mut j = 0;
+    while i < lhs.len() && j < rhs.len() {
+        let n = count_before(&lhs[i..], rhs[j]);
+        if n > 0 {
+            packed.extend_from_slice(&lhs[i..i + n]);
+            i += n;
+            continue;
+        }
+
+        let n = count_before(&rhs[j..], lhs[i]);
+        if n > 0 {
+            packed.extend_from_slice(&rhs[j..j + n]);
+            j += n;
+            continue;
+        }
+
+        // neither side is smaller, so they have
+        // the same document ID and group
+        packed.push(lhs[i] | rhs[j]);
+        i += 1;
+        j += 1;
+    }
+    packed.extend_from_slice(&lhs[i..]);
+    packed.extend_from_slice(&rhs[j..]);
+    RoaringishPacked(packed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::super::{BorrowRoaringishPacked, RoaringishPacked, clear_values};
+    use crate::test_utils::Rng;
+
+    fn packed(rng: &mut Rng, num_docs: u64) -> RoaringishPacked {
+        let mut packed = RoaringishPacked::default();
+        for doc_id in 0..num_docs as u32 {
+            if rng.below(3) != 0 {
+                continue;
+            }
+            let mut pos: Vec<u32> = (0..rng.below(6)).map(|_| rng.below(64) as u32).collect();
+            pos.sort_unstable();
+            pos.dedup();
+            packed.push(doc_id, &pos);
+        }
+        packed
+    }
+
+    fn check(lists: &[RoaringishPacked]) {
+        let mut expected: BTreeMap<u64, u64> = BTreeMap::new();
+        for packed in lists.iter() {
+            for p in packed.iter() {
+                *expected.entry(clear_values(*p)).or_default() |= *p;
+            }
+        }
+        let expected: Vec<u64> = expected.into_values().collect();
+
+        let borrowed: Vec<_> = lists
+            .iter()
+            .map(|p| BorrowRoaringishPacked::new(p))
+            .collect();
+        let union = BorrowRoaringishPacked::union(&borrowed);
+        assert_eq!(union.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn union_matches_scalar_reference() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..1000 {
+            let num_docs = 1 + rng.below(200);
+            let lists: Vec<RoaringishPacked> = (0..rng.below(6))
+                .map(|_| packed(&mut rng, num_docs))
+                .collect();
+            check(&lists);
+        }
+    }
+
+    #[test]
+    fn union_edge_cases() {
+        let packed = |doc_id: u32, pos: &[u32]| {
+            let mut packed = RoaringishPacked::default();
+            packed.push(doc_id, pos);
+            packed
+        };
+        let empty = RoaringishPacked::default();
+
+        check(&[]);
+        check(std::slice::from_ref(&empty));
+        check(&[empty.clone(), empty.clone()]);
+        check(&[empty.clone(), packed(3, &[1, 2])]);
+        check(&[packed(3, &[1, 2]), empty.clone()]);
+        // all of the values in the same group of the same document
+        check(&[packed(3, &[0, 5]), packed(3, &[5, 15])]);
+        check(&[packed(3, &[0]), packed(3, &[1]), packed(3, &[2]), empty]);
+        check(&[packed(0, &[0]), packed(u32::MAX >> 1, &[15])]);
+    }
+}
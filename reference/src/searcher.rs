@@ -1,6 +1,9 @@
-use std::{collections::HashSet, path::Path};
+use std::{collections::HashSet, num::NonZero, path::Path, sync::Arc};
 
-use crate::{DB, DbError, Intersection, SearchError, Stats, db::Document, error::GetDocumentError};
+use crate::{
+    ArchivedDocuments, DB, DbError, DocSet, Explain, Intersection, SearchCache, SearchError,
+    SearchOptions, Snapshot, Stats, SynonymMap, db::Document, error::GetDocumentError,
+};
 use memmap2::Mmap;
 use rkyv::{Archive, Deserialize, de::Pool, rancor::Strategy};
 
@@ -13,14 +16,30 @@ impl<D: Document> SearchResult<'_, D> {
     }
 
     /// Returns the internal document IDs that matched the search query.
+    ///
+    /// They are only the positions of the documents in the indexed
+    /// iterator if no document was split into chunks, see [crate::Indexer::index].
     pub fn get_internal_document_ids(&self) -> Option<&[u32]> {
         self.0.as_ref().map(|p| p.as_slice()).ok()
     }
 
+    /// Converts the result into a [DocSet], so it can be combined with
+    /// the results of other searches.
+    ///
+    /// An [SearchError::EmptyIntersection] is converted into an empty set.
+    pub fn into_doc_set(self) -> Result<DocSet, SearchError> {
+        match self.0 {
+            Ok(doc_ids) => Ok(DocSet::from_sorted(doc_ids)),
+            Err(SearchError::EmptyIntersection) => Ok(DocSet::default()),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Gets the archived version of the documents that matched the search query.
     ///
     /// This avoids having to deserialize, but it's necessary to use a callback
-    /// due to the lifetime of the transaction.
+    /// due to the lifetime of the transaction, use [Self::archived_documents]
+    /// to avoid it.
     ///
     /// If you want the documents deserialized, use [Self::get_documents] instead.
     pub fn get_archived_documents(
@@ -34,6 +53,15 @@ impl<D: Document> SearchResult<'_, D> {
         self.1.get_archived_documents(doc_ids, cb)
     }
 
+    /// Gets the archived version of the documents that matched the search
+    /// query, the documents are only retrieved while iterating.
+    ///
+    /// Useful to stream big results without having all of the documents in memory.
+    pub fn archived_documents(&self) -> Result<ArchivedDocuments<'_, D>, GetDocumentError> {
+        let doc_ids = self.get_internal_document_ids().unwrap_or_default();
+        self.1.archived_documents(doc_ids)
+    }
+
     /// Gets the deserialized version of the documents that matched the search query.
     pub fn get_documents(&self) -> Result<Vec<D>, GetDocumentError>
     where
@@ -52,6 +80,9 @@ pub struct Searcher<D: Document> {
     db: DB<D>,
     common_tokens: HashSet<Box<str>>,
     mmap: Mmap,
+    cache: Option<Arc<SearchCache>>,
+    threads: NonZero<usize>,
+    synonyms: Option<SynonymMap>,
 }
 
 impl<D: Document> Searcher<D> {
@@ -62,9 +93,46 @@ impl<D: Document> Searcher<D> {
             db,
             common_tokens,
             mmap,
+            cache: None,
+            threads: NonZero::<usize>::MIN,
+            synonyms: None,
         })
     }
 
+    /// Caches the results of the searches in `cache`.
+    ///
+    /// The same cache can be shared with other searchers and reused after
+    /// reopening the index, the results are only reused for the same index.
+    pub fn with_cache(mut self, cache: Arc<SearchCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Cache used by this searcher, if any.
+    pub fn cache(&self) -> Option<&SearchCache> {
+        self.cache.as_deref()
+    }
+
+    /// Splits the document IDs of each search in at most `threads`
+    /// ranges that are intersected in parallel. Defaults to 1.
+    ///
+    /// This reduces the latency of queries with big Roaringish Packed,
+    /// at the cost of throughput. [Self::search_many] and
+    /// [Self::explain] always run in a single thread.
+    pub fn with_threads(mut self, threads: NonZero<usize>) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Expands the queries with `synonyms`, the result of a search is
+    /// the union of the results of all of the expanded queries.
+    ///
+    /// The synonyms are normalized with the analyzer of the index.
+    pub fn with_synonyms(mut self, synonyms: SynonymMap) -> Self {
+        self.synonyms = Some(synonyms.normalized(self.db.analyzer()));
+        self
+    }
+
     /// Searches by the query `q`
     pub fn search<I: Intersection>(&self, q: &str) -> SearchResult<D> {
         let stats = Stats::default();
@@ -73,17 +141,174 @@ impl<D: Document> Searcher<D> {
 
     /// Searches by the query `q`, allowing the user to pass a [Stats] object.
     pub fn search_with_stats<I: Intersection>(&self, q: &str, stats: &Stats) -> SearchResult<D> {
+        self.search_with_options_and_stats::<I>(q, &SearchOptions::default(), stats)
+    }
+
+    /// Searches by the query `q`, limiting the search by `options`.
+    pub fn search_with_options<I: Intersection>(
+        &self,
+        q: &str,
+        options: &SearchOptions,
+    ) -> SearchResult<'_, D> {
+        let stats = Stats::default();
+        self.search_with_options_and_stats::<I>(q, options, &stats)
+    }
+
+    /// Searches by the query `q`, limiting the search by `options`
+    /// and allowing the user to pass a [Stats] object.
+    pub fn search_with_options_and_stats<I: Intersection>(
+        &self,
+        q: &str,
+        options: &SearchOptions,
+        stats: &Stats,
+    ) -> SearchResult<'_, D> {
+        SearchResult(self.inner_search::<I>(q, options, stats, None), self)
+    }
+
+    /// Searches by the query `q`, but only the documents in `within` can match.
+    ///
+    /// This is faster than searching and intersecting the results
+    /// afterwards, since `within` is used to filter the Roaringish
+    /// Packed before the intersections.
+    pub fn search_within<I: Intersection>(&self, within: &DocSet, q: &str) -> SearchResult<'_, D> {
+        self.search_within_with_options::<I>(within, q, &SearchOptions::default())
+    }
+
+    /// Searches by the query `q` in the documents in `within`,
+    /// limiting the search by `options`.
+    pub fn search_within_with_options<I: Intersection>(
+        &self,
+        within: &DocSet,
+        q: &str,
+        options: &SearchOptions,
+    ) -> SearchResult<'_, D> {
+        let stats = Stats::default();
+        self.search_within_with_options_and_stats::<I>(within, q, options, &stats)
+    }
+
+    /// Searches by the query `q` in the documents in `within`, limiting the
+    /// search by `options` and allowing the user to pass a [Stats] object.
+    pub fn search_within_with_options_and_stats<I: Intersection>(
+        &self,
+        within: &DocSet,
+        q: &str,
+        options: &SearchOptions,
+        stats: &Stats,
+    ) -> SearchResult<'_, D> {
         SearchResult(
-            self.db
-                .search::<I>(q, stats, &self.common_tokens, &self.mmap),
+            self.inner_search::<I>(q, options, stats, Some(within)),
             self,
         )
     }
 
+    /// Searches by the query `q` and all of its synonyms.
+    fn inner_search<I: Intersection>(
+        &self,
+        q: &str,
+        options: &SearchOptions,
+        stats: &Stats,
+        within: Option<&DocSet>,
+    ) -> Result<Vec<u32>, SearchError> {
+        self.db.search::<I>(
+            q,
+            stats,
+            &self.common_tokens,
+            self.synonyms.as_ref(),
+            &self.mmap,
+            self.cache.as_deref(),
+            self.threads,
+            options,
+            within,
+        )
+    }
+
+    /// Searches by multiple queries at once, the results are
+    /// in the same order as `queries`.
+    ///
+    /// This is faster than calling [Self::search] for each query, since
+    /// the Roaringish Packed of the tokens are only fetched once and
+    /// queries that start the same way share the intersection.
+    pub fn search_many<I: Intersection>(
+        &self,
+        queries: &[&str],
+    ) -> Result<Vec<SearchResult<'_, D>>, SearchError> {
+        let stats = Stats::default();
+        self.search_many_with_stats::<I>(queries, &stats)
+    }
+
+    /// Searches by multiple queries at once, allowing the user to pass a [Stats] object.
+    pub fn search_many_with_stats<I: Intersection>(
+        &self,
+        queries: &[&str],
+        stats: &Stats,
+    ) -> Result<Vec<SearchResult<'_, D>>, SearchError> {
+        self.search_many_with_options_and_stats::<I>(queries, &SearchOptions::default(), stats)
+    }
+
+    /// Searches by multiple queries at once, limiting the searches by `options`.
+    ///
+    /// The deadline and cancellation are shared by all of the queries,
+    /// the other limits are applied to each query.
+    pub fn search_many_with_options<I: Intersection>(
+        &self,
+        queries: &[&str],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult<'_, D>>, SearchError> {
+        let stats = Stats::default();
+        self.search_many_with_options_and_stats::<I>(queries, options, &stats)
+    }
+
+    /// Searches by multiple queries at once, limiting the searches by
+    /// `options` and allowing the user to pass a [Stats] object.
+    pub fn search_many_with_options_and_stats<I: Intersection>(
+        &self,
+        queries: &[&str],
+        options: &SearchOptions,
+        stats: &Stats,
+    ) -> Result<Vec<SearchResult<'_, D>>, SearchError> {
+        let results = self.db.search_many::<I>(
+            queries,
+            stats,
+            &self.common_tokens,
+            self.synonyms.as_ref(),
+            &self.mmap,
+            self.cache.as_deref(),
+            options,
+        )?;
+        Ok(results.into_iter().map(|r| SearchResult(r, self)).collect())
+    }
+
+    /// Explains how the query `q` is planned and executed, without
+    /// retrieving the matched documents.
+    ///
+    /// Useful to understand why a query is slow or why a
+    /// document didn't match.
+    pub fn explain<I: Intersection>(&self, q: &str) -> Result<Explain, SearchError> {
+        self.explain_with_options::<I>(q, &SearchOptions::default())
+    }
+
+    /// Same as [Self::explain], but the query is analyzed and limited
+    /// by `options`, like in [Self::search_with_options].
+    pub fn explain_with_options<I: Intersection>(
+        &self,
+        q: &str,
+        options: &SearchOptions,
+    ) -> Result<Explain, SearchError> {
+        let stats = Stats::default();
+        self.db.explain::<I>(
+            q,
+            &stats,
+            &self.common_tokens,
+            self.synonyms.as_ref(),
+            &self.mmap,
+            options,
+        )
+    }
+
     /// Gets the archived version of the documents.
     ///
     /// This avoids having to deserialize, but it's necessary to use a callback
-    /// due to the lifetime of the transaction.
+    /// due to the lifetime of the transaction, use [Self::snapshot] to avoid it.
     ///
     /// If you want the documents deserialized, use [Self::get_documents] instead.
     pub fn get_archived_documents(
@@ -94,10 +319,25 @@ impl<D: Document> Searcher<D> {
         self.db.get_archived_documents(doc_ids, cb)
     }
 
+    /// Creates a consistent view of the documents, that owns the read
+    /// transaction and can be used for multiple lookups.
+    pub fn snapshot(&self) -> Result<Snapshot<'_, D>, GetDocumentError> {
+        Ok(Snapshot::new(&self.db)?)
+    }
+
+    /// Gets the archived version of the documents, the
+    /// documents are only retrieved while iterating.
+    pub fn archived_documents<'a>(
+        &'a self,
+        doc_ids: &'a [u32],
+    ) -> Result<ArchivedDocuments<'a, D>, GetDocumentError> {
+        Ok(ArchivedDocuments::new(&self.db, doc_ids)?)
+    }
+
     /// Gets the archived version of a documents.
     ///
     /// This avoids having to deserialize, but it's necessary to use a callback
-    /// due to the lifetime of the transaction.
+    /// due to the lifetime of the transaction, use [Self::snapshot] to avoid it.
     ///
     /// If you want the documents deserialized, use [Self::get_document] instead.
     pub fn get_archived_document(
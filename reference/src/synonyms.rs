@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+
+use crate::{Analyzer, SearchError, normalize, tokenize};
+
+/// Synonyms used to expand the queries at search time.
+///
+/// Each query is expanded into all of the possible paths, where
+/// a sequence of tokens can be replaced by any of its synonyms,
+/// and the result of the search is the union of all of the paths.
+/// Synonyms can have a different number of tokens, e.g.
+/// `nyc => new york city`.
+///
+/// Paths with the same number of tokens that only differ in one position
+/// are searched together, by using the union of the Roaringish Packed of
+/// the alternatives of that position, so synonyms with a single token don't
+/// need their own search.
+///
+/// Used by [crate::Searcher::with_synonyms].
+#[derive(Clone, Debug)]
+pub struct SynonymMap {
+    /// Normalized tokens (separated by a space) to their synonyms.
+    map: HashMap<Box<str>, Vec<Box<str>>>,
+    /// Biggest number of tokens of a key in the map.
+    max_len: usize,
+    max_paths: usize,
+    /// Synonyms as they were added, so they can be
+    /// normalized again by the analyzer of the index.
+    added: Vec<(Box<str>, Box<str>)>,
+}
+
+impl Default for SynonymMap {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            max_len: 0,
+            max_paths: 64,
+            added: Vec::new(),
+        }
+    }
+}
+
+/// Normalizes and tokenizes `s`, the tokens are separated by a space.
+fn normalize_tokenize(s: &str) -> String {
+    let s = normalize(s);
+    tokenize(&s).intersperse(" ").collect()
+}
+
+impl SynonymMap {
+    /// Creates an empty map, that allows at most 64 paths per query.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of paths that a query can be expanded into,
+    /// if a query has more paths the search fails with
+    /// [SearchError::TooManyExpansions].
+    pub fn with_max_paths(mut self, max_paths: usize) -> Self {
+        self.max_paths = max_paths;
+        self
+    }
+
+    /// Adds `to` as a synonym of `from`.
+    ///
+    /// This only works in one direction, if `from` should also be
+    /// a synonym of `to` it has to be added separately.
+    pub fn add(&mut self, from: &str, to: &str) {
+        self.added.push((from.into(), to.into()));
+        self.insert(from, to, normalize_tokenize);
+    }
+
+    /// Same as [Self::add], but normalizing and tokenizing with
+    /// `normalize_tokenize`, without keeping the synonym.
+    fn insert(&mut self, from: &str, to: &str, normalize_tokenize: impl Fn(&str) -> String) {
+        let from = normalize_tokenize(from);
+        let to = normalize_tokenize(to);
+        if from.is_empty() || to.is_empty() || from == to {
+            return;
+        }
+
+        self.max_len = self.max_len.max(from.split(' ').count());
+        let synonyms = self.map.entry(from.into_boxed_str()).or_default();
+        if !synonyms.iter().any(|s| **s == to) {
+            synonyms.push(to.into_boxed_str());
+        }
+    }
+
+    /// Number of tokens sequences that have synonyms.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if there are no synonyms.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Normalizes and tokenizes the synonyms again with the `analyzer`,
+    /// the same way as the queries, so they match their tokens.
+    pub(crate) fn normalized(self, analyzer: &Analyzer) -> Self {
+        let mut normalized = Self {
+            max_paths: self.max_paths,
+            ..Self::default()
+        };
+        for (from, to) in self.added.iter() {
+            normalized.insert(from, to, |s| {
+                let s = analyzer.normalize(s);
+                let tokens: Vec<_> = analyzer.tokenize(&s, true).map(|t| t.token).collect();
+                tokens.join(" ")
+            });
+        }
+        normalized.added = self.added;
+        normalized
+    }
+
+    /// Expands the normalized `tokens` of a query, at `positions`, into
+    /// the queries that need to be searched, the result of the query is
+    /// the union of their results. The first expansion always has the
+    /// query itself.
+    ///
+    /// Spans of tokens with synonyms are expanded independently, their paths
+    /// with the same number of tokens that only differ in one position are
+    /// grouped together and that position becomes the union of the alternatives.
+    /// So synonyms with a single token never multiply the number of expansions.
+    ///
+    /// Synonyms never match across a gap in the `positions`, the gaps
+    /// are kept in the positions of the expansions.
+    pub(crate) fn expand(
+        &self,
+        tokens: &[&str],
+        positions: &[u32],
+    ) -> Result<Vec<Expansion>, SearchError> {
+        let original: Expansion = tokens
+            .iter()
+            .zip(positions.iter())
+            .map(|(t, pos)| (*pos, vec![Box::from(*t)]))
+            .collect();
+        if self.map.is_empty() {
+            return Ok(vec![original]);
+        }
+
+        // spans of the tokens that have synonyms, the ones that
+        // overlap are joined, so they can be expanded independently
+        let consecutive = |b: usize, e: usize| positions[b..e].windows(2).all(|w| w[1] == w[0] + 1);
+        let mut spans: Vec<(usize, usize)> = Vec::new();
+        for i in 0..tokens.len() {
+            let max_len = self.max_len.min(tokens.len() - i);
+            for len in 1..=max_len {
+                if !consecutive(i, i + len)
+                    || !self.map.contains_key(tokens[i..i + len].join(" ").as_str())
+                {
+                    continue;
+                }
+                match spans.last_mut() {
+                    Some((_, e)) if *e > i => *e = (*e).max(i + len),
+                    _ => spans.push((i, i + len)),
+                }
+            }
+        }
+
+        // position of the token `i` of the query when it
+        // follows the tokens already in the `expansion`
+        let next_pos = |expansion: &Expansion, i: usize| match expansion.last() {
+            Some((pos, _)) => pos + positions[i] - positions[i - 1],
+            None => positions[i],
+        };
+        let extend_plain = |expansion: &mut Expansion, b: usize, e: usize| {
+            for (i, (_, token)) in original.iter().enumerate().take(e).skip(b) {
+                let pos = next_pos(expansion, i);
+                expansion.push((pos, token.clone()));
+            }
+        };
+
+        let mut num_paths = 1usize;
+        let mut expansions = vec![Expansion::new()];
+        let mut end = 0;
+        for (b, e) in spans {
+            let paths = self.paths(&tokens[b..e])?;
+            num_paths = num_paths.saturating_mul(paths.len());
+            if num_paths > self.max_paths {
+                return Err(SearchError::TooManyExpansions(num_paths, self.max_paths));
+            }
+
+            let groups = group(paths);
+            expansions = expansions
+                .into_iter()
+                .flat_map(|expansion| {
+                    groups.iter().map(move |group| {
+                        let mut expansion = expansion.clone();
+                        extend_plain(&mut expansion, end, b);
+                        let pos = next_pos(&expansion, b);
+                        let group = group.iter().cloned();
+                        expansion.extend((pos..).zip(group));
+                        expansion
+                    })
+                })
+                .collect();
+            end = e;
+        }
+
+        for expansion in expansions.iter_mut() {
+            extend_plain(expansion, end, tokens.len());
+        }
+        Ok(expansions)
+    }
+
+    /// All of the possible paths through the synonyms
+    /// of `tokens`, the first one is always `tokens`.
+    fn paths(&self, tokens: &[&str]) -> Result<Vec<Vec<Box<str>>>, SearchError> {
+        // paths that end before the token `i`
+        let mut paths: Vec<Vec<Vec<Box<str>>>> = vec![Vec::new(); tokens.len() + 1];
+        paths[0].push(Vec::new());
+        for i in 0..tokens.len() {
+            let current = std::mem::take(&mut paths[i]);
+            for path in current.iter() {
+                let mut next = path.clone();
+                next.push(tokens[i].into());
+                paths[i + 1].push(next);
+
+                let max_len = self.max_len.min(tokens.len() - i);
+                for len in 1..=max_len {
+                    let key = tokens[i..i + len].join(" ");
+                    let Some(synonyms) = self.map.get(key.as_str()) else {
+                        continue;
+                    };
+                    for synonym in synonyms {
+                        let mut next = path.clone();
+                        next.extend(synonym.split(' ').map(Box::from));
+                        paths[i + len].push(next);
+                    }
+                }
+            }
+
+            let n = paths.iter().map(|p| p.len()).max().unwrap_or(0);
+            if n > self.max_paths {
+                return Err(SearchError::TooManyExpansions(n, self.max_paths));
+            }
+        }
+
+        let original: Vec<Box<str>> = tokens.iter().map(|t| Box::from(*t)).collect();
+        // this can't fail, there is always at least one path
+        let mut paths = paths.pop().unwrap();
+        paths.retain(|path| *path != original);
+        paths.sort_unstable();
+        paths.dedup();
+        paths.insert(0, original);
+        Ok(paths)
+    }
+}
+
+/// Positions of a query expanded by the synonyms,
+/// each with the tokens that can be at that position.
+pub(crate) type Expansion = Vec<(u32, Vec<Box<str>>)>;
+
+/// Tokens that can be at each position of a path.
+type Group = Vec<Vec<Box<str>>>;
+
+/// Groups the `paths` with the same number of tokens that only differ
+/// in one position, so the group is exactly the combination of the
+/// alternatives of each position. The first group has the first path.
+fn group(paths: Vec<Vec<Box<str>>>) -> Vec<Group> {
+    // each group and the position where its paths differ
+    let mut groups: Vec<(Group, Option<usize>)> = Vec::new();
+    'paths: for path in paths {
+        for (group, var) in groups.iter_mut() {
+            if group.len() != path.len() {
+                continue;
+            }
+
+            let mut diff = (0..path.len()).filter(|i| group[*i][0] != path[*i]);
+            let (Some(i), None) = (diff.next(), diff.next()) else {
+                continue;
+            };
+            if var.is_some_and(|var| var != i) {
+                continue;
+            }
+
+            *var = Some(i);
+            group[i].push(path[i].clone());
+            continue 'paths;
+        }
+        groups.push((path.into_iter().map(|t| vec![t]).collect(), None));
+    }
+    groups.into_iter().map(|(group, _)| group).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expansion, SynonymMap};
+    use crate::SearchError;
+
+    fn synonyms(pairs: &[(&str, &str)]) -> SynonymMap {
+        let mut synonyms = SynonymMap::new();
+        for (from, to) in pairs {
+            synonyms.add(from, to);
+        }
+        synonyms
+    }
+
+    fn expansion(positions: &[(u32, &[&str])]) -> Expansion {
+        positions
+            .iter()
+            .map(|(pos, tokens)| (*pos, tokens.iter().map(|t| Box::from(*t)).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn paths_start_with_the_tokens() {
+        let synonyms = synonyms(&[("a", "b"), ("a b", "c"), ("b", "d e")]);
+        let paths = synonyms.paths(&["a", "b"]).unwrap();
+        let paths: Vec<Vec<&str>> = paths
+            .iter()
+            .map(|path| path.iter().map(|t| t.as_ref()).collect())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                vec!["a", "b"],
+                vec!["a", "d", "e"],
+                vec!["b", "b"],
+                vec!["b", "d", "e"],
+                vec!["c"],
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_into_groups() {
+        let synonyms = synonyms(&[
+            ("big", "large"),
+            ("big", "huge"),
+            ("nyc", "new york city"),
+            ("nyc", "new jersey city"),
+        ]);
+
+        // single token synonyms are alternatives of the same position
+        let expansions = synonyms.expand(&["big", "apple"], &[0, 1]).unwrap();
+        assert_eq!(
+            expansions,
+            [expansion(&[
+                (0, &["big", "huge", "large"]),
+                (1, &["apple"])
+            ])]
+        );
+
+        // paths of the same length that differ in one position are
+        // grouped, the gaps of the query are kept
+        let expansions = synonyms.expand(&["nyc", "rocks"], &[0, 2]).unwrap();
+        assert_eq!(
+            expansions,
+            [
+                expansion(&[(0, &["nyc"]), (2, &["rocks"])]),
+                expansion(&[
+                    (0, &["new"]),
+                    (1, &["jersey", "york"]),
+                    (2, &["city"]),
+                    (4, &["rocks"]),
+                ]),
+            ]
+        );
+
+        let expansions = synonyms.expand(&["rocks"], &[3]).unwrap();
+        assert_eq!(expansions, [expansion(&[(3, &["rocks"])])]);
+    }
+
+    #[test]
+    fn limits_the_number_of_paths() {
+        let mut pairs = Vec::new();
+        let tokens = ["a", "b", "c", "d", "e", "f", "g"];
+        for from in tokens {
+            pairs.push((from, "x y"));
+        }
+        let positions: Vec<u32> = (0..tokens.len() as u32).collect();
+
+        // each token doubles the number of paths
+        let r = synonyms(&pairs).expand(&tokens[..6], &positions[..6]);
+        assert_eq!(r.unwrap().len(), 64);
+        let r = synonyms(&pairs).expand(&tokens, &positions);
+        assert!(matches!(r, Err(SearchError::TooManyExpansions(128, 64))));
+
+        let synonyms = synonyms(&[("a", "b"), ("a", "c")]).with_max_paths(2);
+        assert!(matches!(
+            synonyms.paths(&["a"]),
+            Err(SearchError::TooManyExpansions(3, 2))
+        ));
+        assert!(matches!(
+            synonyms.expand(&["a"], &[0]),
+            Err(SearchError::TooManyExpansions(3, 2))
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+use std::{cmp::Reverse, collections::HashSet, num::NonZero};
+
+use gxhash::{HashMap as GxHashMap, HashMapExt};
+use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+
+use crate::{Analyzer, CommonTokens, query_log::QueryLog};
+
+/// Frequency of a token.
+#[derive(Clone, Copy, Debug, Default)]
+struct TokenFreq {
+    /// Number of occurrences of the token.
+    freq: u64,
+    /// Number of documents with the token.
+    doc_freq: u64,
+    /// Last document with the token, so each document is only counted once.
+    last_doc: u64,
+}
+
+/// Counts the frequency of the tokens, used to generate the common tokens.
+///
+/// With a `capacity` at most that many tokens are tracked. When it's full the
+/// least frequent half is evicted and the new tokens start at the lowest
+/// frequency that is still tracked (space saving), so the frequencies are
+/// overestimated by at most that frequency, but the most frequent tokens
+/// are always tracked.
+pub struct TokenCounter {
+    tokens: GxHashMap<Box<str>, TokenFreq>,
+    capacity: Option<usize>,
+    /// Lowest frequency and document frequency tracked after the last
+    /// eviction, they are at least the ones of any evicted token.
+    floor: TokenFreq,
+    num_docs: u64,
+    /// Used to estimate the number of distinct tokens, when some are evicted.
+    hllp_tokens: Option<HyperLogLogPlus<Box<str>, gxhash::GxBuildHasher>>,
+    /// Frequency of the tracked merged tokens, they are never evicted.
+    merged: GxHashMap<Box<str>, u64>,
+    /// Last tokens of the current document, used to count the merged tokens.
+    window: Vec<Box<str>>,
+    /// Maximum number of tokens merged into a single token.
+    window_len: NonZero<usize>,
+}
+
+impl TokenCounter {
+    pub fn new(capacity: Option<usize>, window_len: NonZero<usize>) -> Self {
+        Self {
+            tokens: GxHashMap::new(),
+            capacity,
+            floor: TokenFreq::default(),
+            num_docs: 0,
+            // This can't fail
+            hllp_tokens: capacity
+                .map(|_| HyperLogLogPlus::new(18, gxhash::GxBuildHasher::default()).unwrap()),
+            merged: GxHashMap::new(),
+            window: Vec::new(),
+            window_len,
+        }
+    }
+
+    /// Counts the frequency of the merged token `merged`, formed by up to
+    /// `window_len` tokens separated by a space.
+    pub fn track(&mut self, merged: &str) {
+        if !self.merged.contains_key(merged) {
+            self.merged.insert(merged.into(), 0);
+        }
+    }
+
+    /// Starts counting the tokens of a new document.
+    pub fn next_doc(&mut self) {
+        self.num_docs += 1;
+        self.window.clear();
+    }
+
+    /// Estimated frequency of `token`, which can be a merged token.
+    ///
+    /// The frequency of an evicted token is overestimated by the
+    /// lowest frequency that was tracked after the last eviction.
+    pub fn freq(&self, token: &str) -> u64 {
+        if let Some(freq) = self.merged.get(token) {
+            return *freq;
+        }
+        match self.tokens.get(token) {
+            Some(freq) => freq.freq,
+            None => self.floor.freq,
+        }
+    }
+
+    /// Counts an occurrence of `token` in the current document.
+    pub fn add(&mut self, token: &str) {
+        if let Some(hllp_tokens) = &mut self.hllp_tokens {
+            hllp_tokens.insert(token);
+        }
+
+        let full = self.capacity.is_some_and(|c| self.tokens.len() >= c);
+        if full && !self.tokens.contains_key(token) {
+            self.evict();
+        }
+
+        let floor = self.floor;
+        let (_, freq) = self
+            .tokens
+            .raw_entry_mut()
+            .from_key(token)
+            .or_insert_with(|| {
+                let freq = TokenFreq {
+                    freq: floor.freq,
+                    doc_freq: floor.doc_freq,
+                    last_doc: 0,
+                };
+                (token.to_owned().into_boxed_str(), freq)
+            });
+        freq.freq += 1;
+        if freq.last_doc != self.num_docs {
+            freq.last_doc = self.num_docs;
+            freq.doc_freq += 1;
+        }
+
+        if !self.merged.is_empty() {
+            self.add_merged(token);
+        }
+    }
+
+    /// Counts the tracked merged tokens that end with `token`.
+    fn add_merged(&mut self, token: &str) {
+        if self.window.len() == self.window_len.get() {
+            self.window.remove(0);
+        }
+        self.window.push(token.into());
+
+        let mut merged = String::new();
+        for start in (0..self.window.len() - 1).rev() {
+            merged.clear();
+            for token in self.window[start..].iter() {
+                merged.push_str(token);
+                merged.push(' ');
+            }
+            merged.pop();
+            if let Some(freq) = self.merged.get_mut(merged.as_str()) {
+                *freq += 1;
+            }
+        }
+    }
+
+    /// Evicts the least frequent half of the tokens, ties are evicted by
+    /// the token, so exactly half of them are evicted.
+    fn evict(&mut self) {
+        let mut ranks: Vec<_> = self
+            .tokens
+            .iter()
+            .map(|(t, f)| (f.freq, t.as_ref()))
+            .collect();
+        let mid = ranks.len() / 2;
+        let (_, (freq, token), _) = ranks.select_nth_unstable(mid);
+        let (freq, token) = (*freq, Box::<str>::from(*token));
+        drop(ranks);
+
+        self.tokens
+            .retain(|t, f| (f.freq, t.as_ref()) >= (freq, token.as_ref()));
+
+        self.floor.freq = freq;
+        self.floor.doc_freq = self.tokens.values().map(|f| f.doc_freq).min().unwrap_or(0);
+        log::debug!(
+            "Evicted half of the tokens, {} left with frequency from {}",
+            self.tokens.len(),
+            self.floor.freq
+        );
+    }
+
+    /// Number of distinct tokens counted.
+    fn num_distinct_tokens(&mut self) -> usize {
+        match &mut self.hllp_tokens {
+            Some(hllp_tokens) => hllp_tokens.count() as usize,
+            None => self.tokens.len(),
+        }
+    }
+
+    /// Tokens sorted by decreasing frequency, ties are sorted by the token.
+    fn sorted_by_freq(&self) -> Vec<(&str, &TokenFreq)> {
+        let mut tokens: Vec<_> = self.tokens.iter().map(|(t, f)| (t.as_ref(), f)).collect();
+        tokens.sort_unstable_by(|(t0, f0), (t1, f1)| {
+            (Reverse(f0.freq), t0).cmp(&(Reverse(f1.freq), t1))
+        });
+        tokens
+    }
+
+    /// Generates the list of common tokens to be used in the merging phase.
+    ///
+    /// The `analyzer` is used to tokenize the queries of a [CommonTokens::QueryLog].
+    pub fn common_tokens(
+        &mut self,
+        common_tokens: &CommonTokens,
+        analyzer: &Analyzer,
+    ) -> HashSet<Box<str>> {
+        let max = match common_tokens {
+            CommonTokens::List(tokens) => {
+                return tokens.iter().map(|t| t.clone().into_boxed_str()).collect();
+            }
+            CommonTokens::MinDocFrequency(f) => {
+                let min = *f * self.num_docs as f64;
+                return self
+                    .tokens
+                    .iter()
+                    .filter(|(_, freq)| freq.doc_freq as f64 >= min)
+                    .map(|(token, _)| token.clone())
+                    .collect();
+            }
+            CommonTokens::QueryLog { queries, max } => {
+                return QueryLog::new(queries, analyzer, self.window_len)
+                    .select(*max as usize, self);
+            }
+            CommonTokens::FixedNum(max) => *max as usize,
+            CommonTokens::Percentage(p) => (self.num_distinct_tokens() as f64 * *p) as usize,
+        };
+
+        let tokens = self.sorted_by_freq();
+        let max = max.min(tokens.len());
+        tokens[..max]
+            .iter()
+            .map(|(token, _)| Box::from(*token))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::TokenCounter;
+    use crate::{Analyzer, CommonTokens, db::DEFAULT_WINDOW_LEN};
+
+    fn count(capacity: usize, tokens: &[(&str, u64)]) -> TokenCounter {
+        let mut counter = TokenCounter::new(Some(capacity), DEFAULT_WINDOW_LEN);
+        counter.next_doc();
+        for (token, freq) in tokens {
+            for _ in 0..*freq {
+                counter.add(token);
+            }
+        }
+        counter
+    }
+
+    #[test]
+    fn evicts_the_least_frequent_half() {
+        let mut counter = count(4, &[("a", 4), ("b", 3), ("c", 2), ("d", 1)]);
+        counter.evict();
+        assert_eq!(counter.tokens.len(), 2);
+        assert_eq!((counter.freq("a"), counter.freq("b")), (4, 3));
+        // the evicted tokens are overestimated
+        assert_eq!((counter.freq("c"), counter.freq("d")), (3, 3));
+
+        // the ties are evicted by the token
+        let mut counter = count(4, &[("d", 1), ("c", 1), ("b", 1), ("a", 1)]);
+        counter.evict();
+        let mut tokens: Vec<_> = counter.tokens.keys().map(|t| t.as_ref()).collect();
+        tokens.sort_unstable();
+        assert_eq!(tokens, ["c", "d"]);
+    }
+
+    #[test]
+    fn new_tokens_start_at_the_lowest_frequency() {
+        let mut counter = count(4, &[("a", 5), ("b", 4), ("c", 2), ("d", 1)]);
+        counter.next_doc();
+        counter.add("e");
+        assert_eq!(counter.tokens.len(), 3);
+        assert_eq!(counter.freq("e"), 5);
+        assert_eq!(counter.tokens["e"].doc_freq, 2);
+        assert_eq!(counter.freq("c"), 4);
+
+        // only the new tokens evict when it's full
+        counter.add("f");
+        counter.add("a");
+        assert_eq!(counter.tokens.len(), 4);
+        assert_eq!(counter.freq("a"), 6);
+    }
+
+    #[test]
+    fn min_doc_frequency() {
+        let mut counter = TokenCounter::new(None, DEFAULT_WINDOW_LEN);
+        for i in 0..10 {
+            counter.next_doc();
+            counter.add("a");
+            if i % 2 == 0 {
+                counter.add("b");
+            }
+            // frequent, but only in a few documents
+            if i < 4 {
+                for _ in 0..10 {
+                    counter.add("c");
+                }
+            }
+        }
+
+        let common = |counter: &mut TokenCounter, f| {
+            let common_tokens = CommonTokens::MinDocFrequency(f);
+            counter.common_tokens(&common_tokens, &Analyzer::default())
+        };
+        let set = |tokens: &[&str]| -> HashSet<Box<str>> {
+            tokens.iter().map(|token| Box::from(*token)).collect()
+        };
+        assert_eq!(common(&mut counter, 0.5), set(&["a", "b"]));
+        assert_eq!(common(&mut counter, 0.51), set(&["a"]));
+        assert_eq!(common(&mut counter, 0.4), set(&["a", "b", "c"]));
+        assert!(common(&mut counter, 1.1).is_empty());
+    }
+}
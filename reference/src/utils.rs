@@ -9,7 +9,12 @@ pub fn normalize(s: &str) -> String {
 /// Tokenizes the input string by splitting it into word bounds
 /// also remove all tokens that are considered whitespace by utf-8.
 pub fn tokenize(s: &str) -> impl Iterator<Item = &str> {
-    s.split_word_bounds().filter(|t| {
+    tokenize_indices(s).map(|(_, t)| t)
+}
+
+/// Same as [tokenize], but also returns the byte offset of each token.
+pub fn tokenize_indices(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    s.split_word_bound_indices().filter(|(_, t)| {
         if !t.is_empty() {
             // This is safe because we know that `t` is not empty.
             return !t.chars().next().unwrap().is_whitespace();
@@ -17,3 +22,144 @@ pub fn tokenize(s: &str) -> impl Iterator<Item = &str> {
         false
     })
 }
+
+/// Tokenizes source code, returning the byte offset of each token.
+///
+/// Identifiers (runs of alphanumeric characters and `_`) are a single token,
+/// each bracket is its own token and runs of the other symbols are a single
+/// token, so operators like `->` or `::` are kept together.
+pub fn tokenize_code_indices(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    #[derive(PartialEq)]
+    enum Class {
+        Identifier,
+        Bracket,
+        Symbol,
+    }
+
+    fn class(c: char) -> Option<Class> {
+        match c {
+            '_' => Some(Class::Identifier),
+            '(' | ')' | '[' | ']' | '{' | '}' => Some(Class::Bracket),
+            c if c.is_alphanumeric() => Some(Class::Identifier),
+            c if c.is_whitespace() => None,
+            _ => Some(Class::Symbol),
+        }
+    }
+
+    let mut chars = s.char_indices().peekable();
+    std::iter::from_fn(move || {
+        let (begin, kind) = loop {
+            let (begin, c) = chars.next()?;
+            if let Some(kind) = class(c) {
+                break (begin, kind);
+            }
+        };
+
+        if kind != Class::Bracket {
+            while chars
+                .next_if(|(_, c)| class(*c).as_ref() == Some(&kind))
+                .is_some()
+            {}
+        }
+        let end = chars.peek().map(|(i, _)| *i).unwrap_or(s.len());
+        Some((begin, &s[begin..end]))
+    })
+}
+
+/// Splits an `identifier` on `_` and on case boundaries, e.g.
+/// `parseConfig`, `parse_config` and `PARSE_CONFIG` become `parse config`
+/// and `HTTPServer` becomes `HTTP Server`.
+///
+/// Digits stay attached to the preceding part, e.g. `utf8Decode`
+/// becomes `utf8 Decode`.
+pub fn split_identifier(identifier: &str) -> impl Iterator<Item = &str> {
+    identifier
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .flat_map(|word| {
+            let mut chars = word.char_indices().peekable();
+            let mut begin = 0;
+            let mut prev: Option<char> = None;
+            std::iter::from_fn(move || {
+                while let Some((i, c)) = chars.next() {
+                    let next = chars.peek().map(|(_, c)| *c);
+                    let boundary = match prev {
+                        Some(p) if c.is_uppercase() => {
+                            !p.is_uppercase() || next.is_some_and(char::is_lowercase)
+                        }
+                        _ => false,
+                    };
+                    prev = Some(c);
+                    if boundary {
+                        let part = &word[begin..i];
+                        begin = i;
+                        return Some(part);
+                    }
+                }
+
+                if begin < word.len() {
+                    let part = &word[begin..];
+                    begin = word.len();
+                    return Some(part);
+                }
+                None
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_identifier, tokenize_code_indices, tokenize_indices};
+
+    #[test]
+    fn tokenize_text() {
+        let tokens: Vec<_> = tokenize_indices("look, at  my cat's\tbowl").collect();
+        assert_eq!(
+            tokens,
+            [
+                (0, "look"),
+                (4, ","),
+                (6, "at"),
+                (10, "my"),
+                (13, "cat's"),
+                (19, "bowl"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_code() {
+        let tokens: Vec<_> = tokenize_code_indices("a->b_1::c(d[0]) +=  é").collect();
+        assert_eq!(
+            tokens,
+            [
+                (0, "a"),
+                (1, "->"),
+                (3, "b_1"),
+                (6, "::"),
+                (8, "c"),
+                (9, "("),
+                (10, "d"),
+                (11, "["),
+                (12, "0"),
+                (13, "]"),
+                (14, ")"),
+                (16, "+="),
+                (20, "é"),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_identifiers() {
+        let split = |identifier| split_identifier(identifier).collect::<Vec<_>>();
+        assert_eq!(split("parseConfig"), ["parse", "Config"]);
+        assert_eq!(split("parse_config"), ["parse", "config"]);
+        assert_eq!(split("PARSE_CONFIG"), ["PARSE", "CONFIG"]);
+        assert_eq!(split("HTTPServer"), ["HTTP", "Server"]);
+        assert_eq!(split("utf8Decode"), ["utf8", "Decode"]);
+        assert_eq!(split("__init__"), ["init"]);
+        assert_eq!(split("x"), ["x"]);
+        assert_eq!(split("_"), Vec::<&str>::new());
+    }
+}